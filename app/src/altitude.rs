@@ -0,0 +1,83 @@
+//barometric altitude from BMP280 pressure
+//
+//international barometric formula, good for the troposphere. relative altitude against a
+//zero point cancels most of the error from the sea level reference being off
+
+use nalgebra::ComplexField;
+
+//ISA sea level pressure, Pa
+pub const STANDARD_SEA_LEVEL_PA: f32 = 101_325.0;
+
+//ISA temperature lapse and scale height terms
+const ALTITUDE_SCALE_M: f32 = 44_330.0;
+const EXPONENT: f32 = 1.0 / 5.255;
+
+//metres above the level where the pressure is sea_level_pa
+#[must_use]
+pub fn pressure_to_altitude(pressure_pa: f32, sea_level_pa: f32) -> f32 {
+    ALTITUDE_SCALE_M * (1.0 - ComplexField::powf(pressure_pa / sea_level_pa, EXPONENT))
+}
+
+//the sea level pressure that puts pressure_pa at altitude_m, Pa
+#[must_use]
+pub fn sea_level_pressure(pressure_pa: f32, altitude_m: f32) -> f32 {
+    pressure_pa / ComplexField::powf(1.0 - altitude_m / ALTITUDE_SCALE_M, 1.0 / EXPONENT)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Altimeter {
+    //Pa
+    sea_level: f32,
+    //altitude relative_altitude is measured from, metres
+    zero: f32,
+}
+
+impl Default for Altimeter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Altimeter {
+    //standard atmosphere, zeroed at sea level
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            sea_level: STANDARD_SEA_LEVEL_PA,
+            zero: 0.0,
+        }
+    }
+
+    //local sea level pressure, hPa as reported by weather stations
+    pub fn set_qnh(&mut self, qnh_hpa: f32) {
+        self.sea_level = qnh_hpa * 100.0;
+    }
+
+    //hPa
+    #[must_use]
+    pub fn qnh(&self) -> f32 {
+        self.sea_level / 100.0
+    }
+
+    //work out the sea level pressure from a known altitude, such as a surveyed takeoff point
+    pub fn calibrate(&mut self, pressure_pa: f32, altitude_m: f32) {
+        self.sea_level = sea_level_pressure(pressure_pa, altitude_m);
+    }
+
+    //make the current location the zero for relative_altitude
+    pub fn zero(&mut self, pressure_pa: f32) {
+        self.zero = self.altitude(pressure_pa);
+    }
+
+    //metres above sea level
+    #[must_use]
+    pub fn altitude(&self, pressure_pa: f32) -> f32 {
+        pressure_to_altitude(pressure_pa, self.sea_level)
+    }
+
+    //metres above the zero point
+    #[must_use]
+    pub fn relative_altitude(&self, pressure_pa: f32) -> f32 {
+        self.altitude(pressure_pa) - self.zero
+    }
+}
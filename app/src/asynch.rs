@@ -0,0 +1,572 @@
+//ICM-20948 driver on embedded-hal-async I2C
+//
+//the same register handling as Imc20948 so it can share an executor with USB instead of
+//blocking on every transaction, the register values and decoding are shared with it. covers
+//the Imc20948 configuration, accel, gyro and mag API, the FIFO, DMP, self-test, low power and
+//interrupt setup are only on the blocking driver
+
+use crate::{
+    check_write, decode_imu, decode_mag,
+    fmt::info,
+    i2c_master::{
+        slv0_paused, slv4_done, slv4_read_setup, slv4_write_setup, MAG_AUTO_READ, MST_CTRL,
+        SLV4_POLLS, SLV_EN,
+    },
+    register::{
+        ak09916, AccelConfig, Bank, GyroConfig1, IntPinCfg, PwrMgmt1, PwrMgmt2, Register,
+        RegisterValue, UserCtrl, ACCEL_SMPLRT_DIV_1, ACCEL_SMPLRT_DIV_2, ACCEL_XOUT_H,
+        EXT_SLV_SENS_DATA_00, GYRO_SMPLRT_DIV, I2C_MST_STATUS, I2C_SLV0_CTRL, I2C_SLV4_CTRL,
+        I2C_SLV4_DI, PWR_MGMT_1, REG_BANK_SEL, USER_CTRL, WHO_AM_I,
+    },
+    split_accel_divider, AccelBandwidth, AccelRange, Config, GyroBandwidth, GyroRange, ImcError,
+    ImuAddress, ImuSample, MagAccess, MagMode, MagSample, MagSelfTest, CLKSEL_AUTO, IMU_ID,
+    MAG_ADDR, MAG_ID, MAG_MEASURE_TIMEOUT_MS, MAG_MODE_DELAY_MS, RESET_DELAY_MS, WAKE_DELAY_MS,
+};
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+use nalgebra::Rotation3;
+
+pub struct AsyncImc20948<I> {
+    i2c: I,
+    address: u8,
+    //None until the first bank select, or after a reset
+    bank: Option<Bank>,
+    gyro_range: GyroRange,
+    accel_range: AccelRange,
+    gyro_bandwidth: GyroBandwidth,
+    accel_bandwidth: AccelBandwidth,
+    gyro_divider: u8,
+    accel_divider: u16,
+    mag_mode: MagMode,
+    mag_access: MagAccess,
+    //board to vehicle frame
+    mounting: Rotation3<f32>,
+    verify: bool,
+}
+
+impl<I: I2c> AsyncImc20948<I> {
+    pub fn new(i2c: I, address: ImuAddress) -> Self {
+        Self {
+            i2c,
+            address: address.addr(),
+            bank: None,
+            gyro_range: GyroRange::Dps250,
+            accel_range: AccelRange::G2,
+            gyro_bandwidth: GyroBandwidth::Hz197,
+            accel_bandwidth: AccelBandwidth::Hz246,
+            gyro_divider: 0,
+            accel_divider: 0,
+            mag_mode: MagMode::PowerDown,
+            mag_access: MagAccess::Bypass,
+            mounting: Rotation3::identity(),
+            verify: false,
+        }
+    }
+
+    //read back configuration registers after writing them
+    pub const fn set_verify(&mut self, verify: bool) {
+        self.verify = verify;
+    }
+
+    pub async fn startup<D: DelayNs>(
+        &mut self,
+        config: &Config,
+        delay: &mut D,
+    ) -> Result<(), ImcError<I::Error>> {
+        //check id
+        if self.imu_who_am_i().await? != IMU_ID {
+            return Err(ImcError::BadId);
+        }
+
+        //soft reset
+        self.imu_soft_reset().await?;
+        delay.delay_ms(RESET_DELAY_MS).await;
+
+        self.verify = config.verify;
+        self.mounting = config.mounting;
+
+        //wake, clocked from the PLL
+        self.imu_wake().await?;
+        delay.delay_ms(WAKE_DELAY_MS).await;
+
+        //full power, all accel and gyro axes on
+        self.write(PwrMgmt2::default()).await?;
+
+        //mag startup
+        match config.mag_access {
+            MagAccess::Bypass => self.imu_enable_i2c_bypass().await?,
+            MagAccess::AuxMaster => self.imu_enable_i2c_master().await?,
+        }
+        if self.mag_who_am_i().await? != MAG_ID {
+            return Err(ImcError::BadMagId);
+        }
+        self.mag_soft_reset(delay).await?;
+        self.set_mag_mode(config.mag_mode, delay).await?;
+        if config.mag_access == MagAccess::AuxMaster {
+            self.mag_start_auto_read().await?;
+        }
+
+        //sample mode
+        self.set_gyro_bandwidth(config.gyro_bandwidth).await?;
+        self.set_accel_bandwidth(config.accel_bandwidth).await?;
+        self.set_gyro_sample_rate_divider(config.gyro_divider)
+            .await?;
+        self.set_accel_sample_rate_divider(config.accel_divider)
+            .await?;
+
+        //set scales
+        self.set_gyro_range(config.gyro_range).await?;
+        self.set_accel_range(config.accel_range).await
+    }
+
+    pub async fn imu_who_am_i(&mut self) -> Result<u8, ImcError<I::Error>> {
+        //expect EA
+        let id = self.read_reg(WHO_AM_I).await?;
+        info!("ID: {:X}", id);
+        Ok(id)
+    }
+
+    pub async fn imu_enable_i2c_bypass(&mut self) -> Result<(), ImcError<I::Error>> {
+        //reset i2c master, I2C_MST_RST self clears so can't be verified
+        let user_ctrl = self.read::<UserCtrl>().await?;
+        self.write_reg(
+            USER_CTRL,
+            user_ctrl
+                .with_i2c_mst_en(false)
+                .with_i2c_mst_rst(true)
+                .bits(),
+        )
+        .await?;
+
+        //Enable BYPASS_EN
+        self.modify(|r: IntPinCfg| r.with_bypass_en(true)).await?;
+
+        self.mag_access = MagAccess::Bypass;
+        Ok(())
+    }
+
+    //drive the mag from the ICM-20948's own I2C master rather than the host bus
+    pub async fn imu_enable_i2c_master(&mut self) -> Result<(), ImcError<I::Error>> {
+        //the aux bus can't be bridged and mastered at the same time
+        self.modify(|r: IntPinCfg| r.with_bypass_en(false)).await?;
+
+        self.write(MST_CTRL).await?;
+        self.modify(|r: UserCtrl| r.with_i2c_mst_en(true)).await?;
+
+        self.mag_access = MagAccess::AuxMaster;
+        Ok(())
+    }
+
+    pub async fn imu_wake(&mut self) -> Result<(), ImcError<I::Error>> {
+        //wake from sleep
+        self.write(PwrMgmt1::default().with_clksel(CLKSEL_AUTO))
+            .await
+    }
+
+    pub async fn imu_read(&mut self) -> Result<ImuSample, ImcError<I::Error>> {
+        //accel, gyro then temp
+        let mut buffer = [0; 14];
+        self.read_regs(ACCEL_XOUT_H, &mut buffer).await?;
+
+        Ok(decode_imu(
+            &buffer,
+            self.accel_range,
+            self.gyro_range,
+            &self.mounting,
+        ))
+    }
+
+    //accel, gyro and mag, in a single burst when the aux master is reading the mag
+    pub async fn read_all(&mut self) -> Result<(ImuSample, MagSample), ImcError<I::Error>> {
+        match self.mag_access {
+            MagAccess::Bypass => {
+                let imu = self.imu_read().await?;
+                Ok((imu, self.mag_read().await?))
+            }
+            MagAccess::AuxMaster => {
+                //accel, gyro, temp then EXT_SLV_SENS_DATA
+                let mut buffer = [0; 23];
+                self.read_regs(ACCEL_XOUT_H, &mut buffer).await?;
+
+                let imu = decode_imu(
+                    &buffer[..14],
+                    self.accel_range,
+                    self.gyro_range,
+                    &self.mounting,
+                );
+                let mut mag = [0; 9];
+                mag.copy_from_slice(&buffer[14..]);
+                Ok((imu, decode_mag(&mag, &self.mounting)))
+            }
+        }
+    }
+
+    pub const fn mounting(&self) -> &Rotation3<f32> {
+        &self.mounting
+    }
+
+    //board to vehicle rotation, applied to accel, gyro and mag
+    pub const fn set_mounting(&mut self, mounting: Rotation3<f32>) {
+        self.mounting = mounting;
+    }
+
+    pub const fn gyro_range(&self) -> GyroRange {
+        self.gyro_range
+    }
+
+    pub async fn set_gyro_range(&mut self, range: GyroRange) -> Result<(), ImcError<I::Error>> {
+        self.modify(|r: GyroConfig1| r.with_fs_sel(range.bits()))
+            .await?;
+        self.gyro_range = range;
+        Ok(())
+    }
+
+    pub const fn accel_range(&self) -> AccelRange {
+        self.accel_range
+    }
+
+    pub async fn set_accel_range(&mut self, range: AccelRange) -> Result<(), ImcError<I::Error>> {
+        self.modify(|r: AccelConfig| r.with_fs_sel(range.bits()))
+            .await?;
+        self.accel_range = range;
+        Ok(())
+    }
+
+    pub async fn set_gyro_bandwidth(
+        &mut self,
+        bandwidth: GyroBandwidth,
+    ) -> Result<(), ImcError<I::Error>> {
+        self.modify(|r: GyroConfig1| bandwidth.apply(r)).await?;
+        self.gyro_bandwidth = bandwidth;
+        Ok(())
+    }
+
+    pub async fn set_accel_bandwidth(
+        &mut self,
+        bandwidth: AccelBandwidth,
+    ) -> Result<(), ImcError<I::Error>> {
+        self.modify(|r: AccelConfig| bandwidth.apply(r)).await?;
+        self.accel_bandwidth = bandwidth;
+        Ok(())
+    }
+
+    //gyro ODR = 1.1kHz / (1 + divider)
+    pub async fn set_gyro_sample_rate_divider(
+        &mut self,
+        divider: u8,
+    ) -> Result<(), ImcError<I::Error>> {
+        self.write_config(GYRO_SMPLRT_DIV, divider).await?;
+        self.gyro_divider = divider;
+        Ok(())
+    }
+
+    //accel ODR = 1.125kHz / (1 + divider), divider is 12 bits
+    pub async fn set_accel_sample_rate_divider(
+        &mut self,
+        divider: u16,
+    ) -> Result<(), ImcError<I::Error>> {
+        let [msb, lsb] = split_accel_divider(divider)?;
+
+        self.modify_reg(ACCEL_SMPLRT_DIV_1, 0x0F, msb).await?;
+        self.write_config(ACCEL_SMPLRT_DIV_2, lsb).await?;
+        self.accel_divider = divider;
+        Ok(())
+    }
+
+    //effective gyro output data rate in Hz
+    pub fn gyro_odr(&self) -> f32 {
+        self.gyro_bandwidth.odr(self.gyro_divider)
+    }
+
+    //effective accel output data rate in Hz
+    pub fn accel_odr(&self) -> f32 {
+        self.accel_bandwidth.odr(self.accel_divider)
+    }
+
+    pub async fn mag_read(&mut self) -> Result<MagSample, ImcError<I::Error>> {
+        let mut buffer = [0; 9];
+
+        match self.mag_access {
+            //reading through to ST2 releases the data lock for the next sample
+            MagAccess::Bypass => self.mag_read_regs(ak09916::ST1, &mut buffer).await?,
+            //SLV0 has already done the read
+            MagAccess::AuxMaster => self.read_regs(EXT_SLV_SENS_DATA_00, &mut buffer).await?,
+        }
+
+        Ok(decode_mag(&buffer, &self.mounting))
+    }
+
+    pub async fn mag_who_am_i(&mut self) -> Result<u16, ImcError<I::Error>> {
+        let mut buffer = [0; 2];
+        self.mag_read_regs(ak09916::WIA1, &mut buffer).await?;
+        let id = u16::from_le_bytes([buffer[0], buffer[1]]);
+        //expect 0948
+        info!("ID: {:X}", id);
+        Ok(id)
+    }
+
+    pub const fn mag_mode(&self) -> MagMode {
+        self.mag_mode
+    }
+
+    pub async fn set_mag_mode<D: DelayNs>(
+        &mut self,
+        mode: MagMode,
+        delay: &mut D,
+    ) -> Result<(), ImcError<I::Error>> {
+        //every mode change has to go through power down
+        self.mag_write_reg(ak09916::CNTL2, MagMode::PowerDown.bits())
+            .await?;
+        self.mag_mode = MagMode::PowerDown;
+        delay.delay_ms(MAG_MODE_DELAY_MS).await;
+
+        if mode != MagMode::PowerDown {
+            self.mag_write_reg(ak09916::CNTL2, mode.bits()).await?;
+            self.mag_mode = mode;
+        }
+        Ok(())
+    }
+
+    pub async fn mag_soft_reset<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<(), ImcError<I::Error>> {
+        //CNTL3 SRST, all registers back to power down defaults
+        self.mag_write_reg(ak09916::CNTL3, 0x01).await?;
+        self.mag_mode = MagMode::PowerDown;
+        delay.delay_ms(MAG_MODE_DELAY_MS).await;
+        Ok(())
+    }
+
+    pub async fn mag_measure<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<MagSample, ImcError<I::Error>> {
+        let buffer = self.mag_one_shot(MagMode::Single, delay).await?;
+        Ok(decode_mag(&buffer, &self.mounting))
+    }
+
+    pub async fn mag_self_test<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<MagSelfTest, ImcError<I::Error>> {
+        let buffer = self.mag_one_shot(MagMode::SelfTest, delay).await?;
+        Ok(MagSelfTest::from_bytes(&buffer))
+    }
+
+    //one measurement in a mode that drops back to power down by itself, then back to the
+    //previous mode
+    async fn mag_one_shot<D: DelayNs>(
+        &mut self,
+        mode: MagMode,
+        delay: &mut D,
+    ) -> Result<[u8; 9], ImcError<I::Error>> {
+        let previous = self.mag_mode.settled();
+
+        //SLV0 reading ST2 would clear DRDY before SLV4 gets to poll it
+        let slv0 = self.mag_pause_auto_read().await?;
+        let buffer = match self.set_mag_mode(mode, delay).await {
+            Ok(()) => self.mag_wait_ready(delay).await,
+            Err(e) => Err(e),
+        };
+        let resumed = self.mag_resume_auto_read(slv0).await;
+        let buffer = buffer?;
+        resumed?;
+        self.mag_mode = MagMode::PowerDown;
+
+        self.set_mag_mode(previous, delay).await?;
+        Ok(buffer)
+    }
+
+    //poll DRDY, returning the ST1..ST2 block
+    async fn mag_wait_ready<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<[u8; 9], ImcError<I::Error>> {
+        for _ in 0..MAG_MEASURE_TIMEOUT_MS {
+            let mut buffer = [0; 9];
+            self.mag_read_regs(ak09916::ST1, &mut buffer).await?;
+            if buffer[0] & 0x01 != 0 {
+                return Ok(buffer);
+            }
+            delay.delay_ms(1).await;
+        }
+        Err(ImcError::Timeout)
+    }
+
+    async fn mag_start_auto_read(&mut self) -> Result<(), ImcError<I::Error>> {
+        for (reg, value) in MAG_AUTO_READ {
+            self.write_config(reg, value).await?;
+        }
+        Ok(())
+    }
+
+    //stop SLV0, returning the I2C_SLV0_CTRL value to resume it with, None if it wasn't running
+    async fn mag_pause_auto_read(&mut self) -> Result<Option<u8>, ImcError<I::Error>> {
+        if self.mag_access != MagAccess::AuxMaster {
+            return Ok(None);
+        }
+        let ctrl = self.read_reg(I2C_SLV0_CTRL).await?;
+        let Some(paused) = slv0_paused(ctrl) else {
+            return Ok(None);
+        };
+        self.write_config(I2C_SLV0_CTRL, paused).await?;
+        Ok(Some(ctrl))
+    }
+
+    async fn mag_resume_auto_read(&mut self, ctrl: Option<u8>) -> Result<(), ImcError<I::Error>> {
+        match ctrl {
+            Some(ctrl) => self.write_config(I2C_SLV0_CTRL, ctrl).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn mag_read_regs(
+        &mut self,
+        reg: u8,
+        buffer: &mut [u8],
+    ) -> Result<(), ImcError<I::Error>> {
+        match self.mag_access {
+            MagAccess::Bypass => self
+                .i2c
+                .write_read(MAG_ADDR, &[reg], buffer)
+                .await
+                .map_err(ImcError::from_i2c),
+            //SLV4 only moves a byte at a time
+            MagAccess::AuxMaster => {
+                for (reg, byte) in (reg..).zip(buffer.iter_mut()) {
+                    *byte = self.slv4_read(reg).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    async fn mag_write_reg(&mut self, reg: u8, value: u8) -> Result<(), ImcError<I::Error>> {
+        match self.mag_access {
+            MagAccess::Bypass => self
+                .i2c
+                .write(MAG_ADDR, &[reg, value])
+                .await
+                .map_err(ImcError::from_i2c),
+            MagAccess::AuxMaster => self.slv4_write(reg, value).await,
+        }
+    }
+
+    async fn slv4_read(&mut self, reg: u8) -> Result<u8, ImcError<I::Error>> {
+        for (reg, value) in slv4_read_setup(reg) {
+            self.write_config(reg, value).await?;
+        }
+        self.slv4_transfer().await?;
+        self.read_reg(I2C_SLV4_DI).await
+    }
+
+    async fn slv4_write(&mut self, reg: u8, value: u8) -> Result<(), ImcError<I::Error>> {
+        for (reg, value) in slv4_write_setup(reg, value) {
+            self.write_config(reg, value).await?;
+        }
+        self.slv4_transfer().await
+    }
+
+    async fn slv4_transfer(&mut self) -> Result<(), ImcError<I::Error>> {
+        //SLV4_EN self clears when the transaction is done
+        self.write_reg(I2C_SLV4_CTRL, SLV_EN).await?;
+
+        for _ in 0..SLV4_POLLS {
+            //status clears on read
+            if slv4_done(self.read_reg(I2C_MST_STATUS).await?)? {
+                return Ok(());
+            }
+        }
+        Err(ImcError::Timeout)
+    }
+
+    async fn imu_soft_reset(&mut self) -> Result<(), ImcError<I::Error>> {
+        //DEVICE_RESET self clears so can't be verified
+        let pwr_mgmt_1 = self.read::<PwrMgmt1>().await?;
+        self.write_reg(PWR_MGMT_1, pwr_mgmt_1.with_device_reset(true).bits())
+            .await?;
+
+        //reset puts the chip back in bank 0, but don't trust it until it's read back
+        self.bank = None;
+        Ok(())
+    }
+
+    //switch bank only when the cached bank differs
+    async fn select_bank(&mut self, bank: Bank) -> Result<(), ImcError<I::Error>> {
+        if self.bank != Some(bank) {
+            self.bank = None;
+            self.i2c
+                .write(self.address, &[REG_BANK_SEL, bank.bits()])
+                .await
+                .map_err(ImcError::from_i2c)?;
+            self.bank = Some(bank);
+        }
+        Ok(())
+    }
+
+    async fn read_reg(&mut self, reg: Register) -> Result<u8, ImcError<I::Error>> {
+        let mut buffer = [0; 1];
+        self.read_regs(reg, &mut buffer).await?;
+        Ok(buffer[0])
+    }
+
+    //burst read starting at reg, all registers must be in the same bank
+    async fn read_regs(
+        &mut self,
+        reg: Register,
+        buffer: &mut [u8],
+    ) -> Result<(), ImcError<I::Error>> {
+        self.select_bank(reg.bank).await?;
+        self.i2c
+            .write_read(self.address, &[reg.addr], buffer)
+            .await
+            .map_err(ImcError::from_i2c)
+    }
+
+    //unverified write, for self clearing bits
+    async fn write_reg(&mut self, reg: Register, value: u8) -> Result<(), ImcError<I::Error>> {
+        self.select_bank(reg.bank).await?;
+        self.i2c
+            .write(self.address, &[reg.addr, value])
+            .await
+            .map_err(ImcError::from_i2c)
+    }
+
+    //configuration write, read back when verify is enabled
+    async fn write_config(&mut self, reg: Register, value: u8) -> Result<(), ImcError<I::Error>> {
+        self.write_reg(reg, value).await?;
+
+        if self.verify {
+            check_write(reg, value, self.read_reg(reg).await?)?;
+        }
+        Ok(())
+    }
+
+    //read-modify-write of the bits in mask
+    async fn modify_reg(
+        &mut self,
+        reg: Register,
+        mask: u8,
+        value: u8,
+    ) -> Result<(), ImcError<I::Error>> {
+        let current = self.read_reg(reg).await?;
+        self.write_config(reg, (current & !mask) | (value & mask))
+            .await
+    }
+
+    async fn read<R: RegisterValue>(&mut self) -> Result<R, ImcError<I::Error>> {
+        Ok(R::from_bits(self.read_reg(R::REGISTER).await?))
+    }
+
+    async fn write<R: RegisterValue>(&mut self, value: R) -> Result<(), ImcError<I::Error>> {
+        self.write_config(R::REGISTER, value.bits()).await
+    }
+
+    async fn modify<R: RegisterValue>(
+        &mut self,
+        f: impl FnOnce(R) -> R,
+    ) -> Result<(), ImcError<I::Error>> {
+        let current = self.read::<R>().await?;
+        self.write(f(current)).await
+    }
+}
@@ -0,0 +1,167 @@
+#![no_std]
+#![no_main]
+#![warn(clippy::pedantic, clippy::nursery)]
+#![allow(clippy::missing_errors_doc)]
+
+use bsp::entry;
+use bsp::hal;
+use core::cell::RefCell;
+use core::fmt::{Debug, Write};
+use defmt::{error, info};
+use defmt_rtt as _;
+use embedded_hal::blocking::delay::DelayMs;
+use fugit::RateExtU32;
+use hal::{clocks::init_clocks_and_plls, pac, sio::Sio, watchdog::Watchdog, Clock};
+use imu_playground::{
+    Config, Imc20948, ImuAddress, ImuSample, MagAccess, RefCellDevice, Transport,
+};
+use nalgebra::Vector3;
+use panic_probe as _;
+use rp_pico as bsp;
+#[allow(clippy::wildcard_imports)]
+use usb_device::{class_prelude::*, prelude::*};
+use usbd_serial::SerialPort;
+
+//read both IMUs every 10ms
+const SAMPLE_PERIOD_US: u64 = 10_000;
+
+//two ICM-20948s on I2C1, AD0 low and AD0 high, streamed as CSV prefixed with the IMU index
+#[entry]
+fn main() -> ! {
+    info!("Program start");
+    let mut pac = pac::Peripherals::take().unwrap();
+    let core = pac::CorePeripherals::take().unwrap();
+    let mut watchdog = Watchdog::new(pac.WATCHDOG);
+
+    let clocks = init_clocks_and_plls(
+        bsp::XOSC_CRYSTAL_FREQ,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+
+    let pins = {
+        let sio = Sio::new(pac.SIO);
+
+        bsp::Pins::new(
+            pac.IO_BANK0,
+            pac.PADS_BANK0,
+            sio.gpio_bank0,
+            &mut pac.RESETS,
+        )
+    };
+
+    let sda_pin = pins.gpio14.into_mode::<hal::gpio::FunctionI2C>();
+    let scl_pin = pins.gpio15.into_mode::<hal::gpio::FunctionI2C>();
+
+    let i2c_master = hal::I2C::i2c1(
+        pac.I2C1,
+        sda_pin,
+        scl_pin,
+        400.kHz(),
+        &mut pac.RESETS,
+        &clocks.peripheral_clock,
+    );
+
+    let mut delay = cortex_m::delay::Delay::new(core.SYST, clocks.system_clock.freq().to_Hz());
+    let timer = hal::Timer::new(pac.TIMER, &mut pac.RESETS);
+
+    let bus = RefCell::new(i2c_master);
+    let mut imcs = [
+        Imc20948::new(RefCellDevice::new(&bus), ImuAddress::Ad0Low),
+        Imc20948::new(RefCellDevice::new(&bus), ImuAddress::Ad0High),
+    ];
+    for imc in &mut imcs {
+        start_imu(imc, &mut delay);
+    }
+
+    let usb_alloc = UsbBusAllocator::new(hal::usb::UsbBus::new(
+        pac.USBCTRL_REGS,
+        pac.USBCTRL_DPRAM,
+        clocks.usb_clock,
+        true,
+        &mut pac.RESETS,
+    ));
+
+    let mut serial = SerialPort::new(&usb_alloc);
+
+    let mut usb_dev = UsbDeviceBuilder::new(&usb_alloc, UsbVidPid(1209, 0x0010))
+        .manufacturer("DLKJ")
+        .product("Serial port IMU Playground")
+        .serial_number("TEST")
+        .device_class(2) // from: https://www.usb.org/defined-class-codes
+        .build();
+
+    let mut next_sample = timer.get_counter();
+    loop {
+        if timer.get_counter() >= next_sample {
+            next_sample += SAMPLE_PERIOD_US;
+
+            for (index, imc) in imcs.iter_mut().enumerate() {
+                match imc.read_all() {
+                    Ok((imu, mag)) => write_to_serial(&mut serial, index, &imu, mag.field),
+                    Err(e) => error!("imu {} read error: {}", index, defmt::Debug2Format(&e)),
+                }
+            }
+        }
+
+        // Check for new data
+        if usb_dev.poll(&mut [&mut serial]) {
+            let mut buf = [0u8; 64];
+            match serial.read(&mut buf) {
+                Err(UsbError::WouldBlock) | Ok(_) => {
+                    // Do nothing
+                }
+                Err(e) => error!("serial read error: {}", e),
+            }
+        }
+    }
+}
+
+//each IMU drives its own AK09916 over its aux bus, both mags sit at the same address
+fn start_imu<T, E, D>(imc: &mut Imc20948<T, E>, delay: &mut D)
+where
+    T: Transport<Error = E>,
+    E: Debug,
+    D: DelayMs<u32>,
+{
+    //~100Hz
+    let config = Config {
+        mag_access: MagAccess::AuxMaster,
+        gyro_divider: 10,
+        accel_divider: 10,
+        ..Config::default()
+    };
+    imc.startup(&config, delay).unwrap();
+}
+
+fn write_to_serial<U: UsbBus>(
+    serial: &mut SerialPort<U>,
+    index: usize,
+    imu: &ImuSample,
+    mag: Vector3<f32>,
+) {
+    let mut s = heapless::String::<256>::new();
+    core::write!(
+        &mut s,
+        "{},{},{},{},{},{},{},{},{},{}\r\n",
+        index,
+        imu.acc.x,
+        imu.acc.y,
+        imu.acc.z,
+        imu.gyro.x,
+        imu.gyro.y,
+        imu.gyro.z,
+        mag.x,
+        mag.y,
+        mag.z
+    )
+    .unwrap();
+
+    serial.write(s.as_bytes()).ok();
+}
@@ -0,0 +1,159 @@
+#![no_std]
+#![no_main]
+#![warn(clippy::pedantic, clippy::nursery)]
+#![allow(clippy::missing_errors_doc)]
+
+use bsp::entry;
+use bsp::hal;
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use cortex_m::interrupt::Mutex;
+use defmt::info;
+use defmt_rtt as _;
+use embedded_hal::digital::v2::OutputPin;
+use fugit::RateExtU32;
+use hal::gpio::{bank0::Gpio16, Interrupt::EdgeLow, Pin, PullUpInput};
+use hal::pac::interrupt;
+use hal::{clocks::init_clocks_and_plls, pac, sio::Sio, watchdog::Watchdog, Clock};
+use imu_playground::{
+    Config, Imc20948, ImuAddress, IntLatch, IntLevel, InterruptConfig, LowPowerConfig, MagMode,
+};
+use panic_probe as _;
+use rp_pico as bsp;
+
+//ICM-20948 INT, open drain active low
+type ImuIntPin = Pin<Gpio16, PullUpInput>;
+
+static IMU_INT_PIN: Mutex<RefCell<Option<ImuIntPin>>> = Mutex::new(RefCell::new(None));
+static IMU_MOTION: AtomicBool = AtomicBool::new(false);
+
+//accel change between samples that counts as motion
+const WOM_THRESHOLD_MG: u16 = 80;
+
+//park until the ICM-20948 sees motion, then report it
+#[entry]
+fn main() -> ! {
+    info!("Program start");
+    let mut pac = pac::Peripherals::take().unwrap();
+    let core = pac::CorePeripherals::take().unwrap();
+    let mut watchdog = Watchdog::new(pac.WATCHDOG);
+
+    let clocks = init_clocks_and_plls(
+        bsp::XOSC_CRYSTAL_FREQ,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+
+    let pins = {
+        let sio = Sio::new(pac.SIO);
+
+        bsp::Pins::new(
+            pac.IO_BANK0,
+            pac.PADS_BANK0,
+            sio.gpio_bank0,
+            &mut pac.RESETS,
+        )
+    };
+
+    let sda_pin = pins.gpio14.into_mode::<hal::gpio::FunctionI2C>();
+    let scl_pin = pins.gpio15.into_mode::<hal::gpio::FunctionI2C>();
+
+    let i2c_master = hal::I2C::i2c1(
+        pac.I2C1,
+        sda_pin,
+        scl_pin,
+        400.kHz(),
+        &mut pac.RESETS,
+        &clocks.peripheral_clock,
+    );
+
+    let mut delay = cortex_m::delay::Delay::new(core.SYST, clocks.system_clock.freq().to_Hz());
+
+    let mut imc = Imc20948::new(i2c_master, ImuAddress::Ad0Low);
+
+    //accel only at ~25Hz, mag off
+    let config = Config {
+        accel_divider: 44,
+        mag_mode: MagMode::PowerDown,
+        ..Config::default()
+    };
+    imc.startup(&config, &mut delay).unwrap();
+
+    imc.configure_interrupts(&InterruptConfig {
+        level: IntLevel::ActiveLow,
+        open_drain: true,
+        latch: IntLatch::Latched,
+        wake_on_motion: true,
+        ..InterruptConfig::default()
+    })
+    .unwrap();
+    imc.enable_wake_on_motion(WOM_THRESHOLD_MG).unwrap();
+    imc.enter_low_power(&LowPowerConfig {
+        gyro_off: true,
+        ..LowPowerConfig::default()
+    })
+    .unwrap();
+
+    let mut led_pin = pins.led.into_push_pull_output();
+
+    enable_imu_interrupt(pins.gpio16.into_pull_up_input());
+    //motion while the pin wasn't armed leaves INT latched low with no edge to come
+    imc.interrupt_status().unwrap();
+
+    loop {
+        //sleep with interrupts masked so the edge can't land between the check and the wfi,
+        //a pending interrupt still wakes the core
+        cortex_m::interrupt::free(|_| {
+            if !IMU_MOTION.load(Ordering::Relaxed) {
+                cortex_m::asm::wfi();
+            }
+        });
+        if !take_motion() {
+            continue;
+        }
+
+        //reading the status clears the latched INT pin
+        let status = imc.interrupt_status().unwrap();
+        if status.wake_on_motion {
+            info!("motion");
+            led_pin.set_high().ok();
+            delay.delay_ms(100);
+            led_pin.set_low().ok();
+        }
+    }
+}
+
+fn enable_imu_interrupt(int_pin: ImuIntPin) {
+    int_pin.set_interrupt_enabled(EdgeLow, true);
+    cortex_m::interrupt::free(|cs| IMU_INT_PIN.borrow(cs).replace(Some(int_pin)));
+    unsafe {
+        pac::NVIC::unmask(pac::Interrupt::IO_IRQ_BANK0);
+    }
+}
+
+#[interrupt]
+fn IO_IRQ_BANK0() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(pin) = IMU_INT_PIN.borrow(cs).borrow_mut().as_mut() {
+            if pin.interrupt_status(EdgeLow) {
+                pin.clear_interrupt(EdgeLow);
+                IMU_MOTION.store(true, Ordering::Relaxed);
+            }
+        }
+    });
+}
+
+//thumbv6m has no atomic swap
+fn take_motion() -> bool {
+    cortex_m::interrupt::free(|_| {
+        let motion = IMU_MOTION.load(Ordering::Relaxed);
+        IMU_MOTION.store(false, Ordering::Relaxed);
+        motion
+    })
+}
@@ -6,16 +6,24 @@
 use ahrs::{Ahrs, Madgwick};
 use bsp::entry;
 use bsp::hal;
-use core::fmt::Write;
+use core::cell::RefCell;
+use core::fmt::{Debug, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+use cortex_m::interrupt::Mutex;
 use defmt::{error, info};
 use defmt_rtt as _;
+use embedded_hal::blocking::{delay::DelayMs, i2c};
 use embedded_hal::digital::v2::OutputPin;
 use embedded_hal::digital::v2::ToggleableOutputPin;
-use embedded_hal::timer::CountDown;
-use fugit::ExtU32;
 use fugit::RateExtU32;
-use hal::{clocks::init_clocks_and_plls, pac, sio::Sio, watchdog::Watchdog};
-use imu_playground::Imc20948;
+use hal::gpio::{bank0::Gpio16, Interrupt::EdgeLow, Pin, PullUpInput};
+use hal::pac::interrupt;
+use hal::{clocks::init_clocks_and_plls, pac, sio::Sio, watchdog::Watchdog, Clock};
+use imu_playground::{
+    Altimeter, Bmp280, BmpAddress, BmpConfig, BmpMode, Config, IirFilter, Imc20948, ImuAddress,
+    ImuSample, IntLatch, IntLevel, InterruptConfig, MagAccess, Oversampling, RefCellDevice,
+    SelfTestReport, Transport,
+};
 use nalgebra::{UnitQuaternion, Vector3};
 use panic_probe as _;
 use rp_pico as bsp;
@@ -23,11 +31,20 @@ use rp_pico as bsp;
 use usb_device::{class_prelude::*, prelude::*};
 use usbd_serial::SerialPort;
 
+//ICM-20948 INT, open drain active low
+type ImuIntPin = Pin<Gpio16, PullUpInput>;
+
+static IMU_INT_PIN: Mutex<RefCell<Option<ImuIntPin>>> = Mutex::new(RefCell::new(None));
+static IMU_DATA_READY: AtomicBool = AtomicBool::new(false);
+
+//USB polls to wait for room in the serial buffer before dropping the rest of a write
+const WRITE_POLLS: u32 = 10_000;
+
 #[entry]
 fn main() -> ! {
     info!("Program start");
     let mut pac = pac::Peripherals::take().unwrap();
-    // let core = pac::CorePeripherals::take().unwrap();
+    let core = pac::CorePeripherals::take().unwrap();
     let mut watchdog = Watchdog::new(pac.WATCHDOG);
 
     let clocks = init_clocks_and_plls(
@@ -65,22 +82,20 @@ fn main() -> ! {
         &clocks.peripheral_clock,
     );
 
-    let mut imc = Imc20948::new(i2c_master);
+    let mut delay = cortex_m::delay::Delay::new(core.SYST, clocks.system_clock.freq().to_Hz());
 
-    imc.startup().unwrap();
+    //the ICM-20948 and the BMP280 share I2C1
+    let bus = RefCell::new(i2c_master);
+    let mut imc = Imc20948::new(RefCellDevice::new(&bus), ImuAddress::Ad0Low);
+    let mut bmp = Bmp280::new(RefCellDevice::new(&bus), BmpAddress::SdoLow);
 
-    imc.imu_enable_i2c_bypass().unwrap();
+    start_imu(&mut imc, &mut delay);
+    let altimeter = start_barometer(&mut bmp, &mut delay);
 
-    let mag_id = imc.mag_who_am_i().unwrap();
-    assert_eq!(
-        mag_id, 0x0948,
-        "Unexpected i2c device id {:X}, expected 0xEA",
-        mag_id
-    );
-
-    imc.imu_wake().unwrap();
-
-    imc.mag_wake().unwrap();
+    enable_imu_interrupt(pins.gpio16.into_pull_up_input());
+    //INT may have latched low before the pin was armed and would never make an edge,
+    //reading the status releases it
+    imc.interrupt_status().unwrap();
 
     let usb_alloc = UsbBusAllocator::new(hal::usb::UsbBus::new(
         pac.USBCTRL_REGS,
@@ -101,81 +116,252 @@ fn main() -> ! {
 
     let mut led_pin = pins.led.into_push_pull_output();
 
-    let timer = hal::Timer::new(pac.TIMER, &mut pac.RESETS);
-
-    let mut log_count_down = timer.count_down();
-    log_count_down.start(100.millis());
+    let mut ahrs = Madgwick::<f32>::new(1.0 / imc.gyro_odr(), 0.1);
 
-    let mut ahrs = Madgwick::<f32>::new(0.1, 0.1);
+    let mut mag = Vector3::zeros();
 
     let mut n = 0;
     loop {
         // A welcome message at the beginning
-        if log_count_down.wait().is_ok() {
-            let m = imc.mag_read();
-            let r = imc.imu_read();
-            if m.is_err() || r.is_err() {
+        if take_data_ready() {
+            let Ok((imu, m)) = imc.read_all() else {
                 continue;
+            };
+
+            //keep the last good field on stale or overflowed samples
+            if m.is_valid() {
+                mag = m.field;
             }
-            let (gyro, acc) = r.unwrap();
-            let rm = m.unwrap();
 
             n += 1;
-            if n > 20 {
+            if n > 200 {
                 info!(
                     "acc: {},{},{}, mag: {},{},{}",
-                    acc.x, acc.y, acc.z, rm.x, rm.y, rm.z
+                    imu.acc.x, imu.acc.y, imu.acc.z, mag.x, mag.y, mag.z
                 );
                 n = 0;
             }
 
-            // let quat = ahrs.update(&gyro, &acc, &rm).unwrap();
-            let quat = ahrs.update_imu(&gyro, &acc).unwrap();
+            // let quat = ahrs.update(&imu.gyro, &imu.acc, &mag).unwrap();
+            let quat = ahrs.update_imu(&imu.gyro, &imu.acc).unwrap();
+
+            //height above where the board was powered up
+            let altitude = bmp
+                .read()
+                .map_or(f32::NAN, |b| altimeter.relative_altitude(b.pressure));
 
-            write_to_serial(&mut serial, &mut led_pin, acc, rm, quat);
+            write_to_serial(
+                &mut usb_dev,
+                &mut serial,
+                &mut led_pin,
+                &imu,
+                mag,
+                quat,
+                altitude,
+            );
         }
 
         // Check for new data
-        if usb_dev.poll(&mut [&mut serial]) {
-            let mut buf = [0u8; 64];
-            match serial.read(&mut buf) {
-                Err(UsbError::WouldBlock) | Ok(_) => {
-                    // Do nothing
-                }
-                Err(e) => error!("serial read error: {}", e),
-            }
+        if usb_dev.poll(&mut [&mut serial]) && self_test_requested(&mut serial) {
+            write_self_test(&mut usb_dev, &mut serial, imc.self_test(&mut delay));
         }
     }
 }
 
+fn start_imu<T, E, D>(imc: &mut Imc20948<T, E>, delay: &mut D)
+where
+    T: Transport<Error = E>,
+    E: Debug,
+    D: DelayMs<u32>,
+{
+    //~100Hz
+    let config = Config {
+        mag_access: MagAccess::AuxMaster,
+        gyro_divider: 10,
+        accel_divider: 10,
+        ..Config::default()
+    };
+    imc.startup(&config, delay).unwrap();
+
+    //latched until the sample is read, so every falling edge is a new sample
+    imc.configure_interrupts(&InterruptConfig {
+        level: IntLevel::ActiveLow,
+        open_drain: true,
+        latch: IntLatch::Latched,
+        clear_on_any_read: true,
+        raw_data_ready: true,
+        ..InterruptConfig::default()
+    })
+    .unwrap();
+}
+
+//continuous measurements at ~26Hz, zeroed at the current height
+fn start_barometer<I, E, D>(bmp: &mut Bmp280<I, E>, delay: &mut D) -> Altimeter
+where
+    I: i2c::Read<Error = E> + i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
+    E: Debug,
+    D: DelayMs<u32>,
+{
+    let config = BmpConfig {
+        pressure_oversampling: Oversampling::X8,
+        filter: IirFilter::X4,
+        mode: BmpMode::Normal,
+        ..BmpConfig::default()
+    };
+    bmp.startup(&config, delay).unwrap();
+
+    let mut altimeter = Altimeter::new();
+    altimeter.zero(bmp.measure(delay).unwrap().pressure);
+    altimeter
+}
+
+fn enable_imu_interrupt(int_pin: ImuIntPin) {
+    int_pin.set_interrupt_enabled(EdgeLow, true);
+    cortex_m::interrupt::free(|cs| IMU_INT_PIN.borrow(cs).replace(Some(int_pin)));
+    unsafe {
+        pac::NVIC::unmask(pac::Interrupt::IO_IRQ_BANK0);
+    }
+}
+
+#[interrupt]
+fn IO_IRQ_BANK0() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(pin) = IMU_INT_PIN.borrow(cs).borrow_mut().as_mut() {
+            if pin.interrupt_status(EdgeLow) {
+                pin.clear_interrupt(EdgeLow);
+                IMU_DATA_READY.store(true, Ordering::Relaxed);
+            }
+        }
+    });
+}
+
+//thumbv6m has no atomic swap
+fn take_data_ready() -> bool {
+    cortex_m::interrupt::free(|_| {
+        let ready = IMU_DATA_READY.load(Ordering::Relaxed);
+        IMU_DATA_READY.store(false, Ordering::Relaxed);
+        ready
+    })
+}
+
 fn write_to_serial<U: UsbBus, P: ToggleableOutputPin + OutputPin>(
+    usb_dev: &mut UsbDevice<U>,
     serial: &mut SerialPort<U>,
     led_pin: &mut P,
-    acc: Vector3<f32>,
+    imu: &ImuSample,
     mag: Vector3<f32>,
     quat: &UnitQuaternion<f32>,
+    altitude: f32,
 ) {
     let (roll, pitch, yaw) = quat.euler_angles();
 
     let mut s = heapless::String::<256>::new();
     core::write!(
         &mut s,
-        "{},{},{},{},{},{},{},{},{}\r\n",
-        acc.x,
-        acc.y,
-        acc.z,
+        "{},{},{},{},{},{},{},{},{},{},{}\r\n",
+        imu.acc.x,
+        imu.acc.y,
+        imu.acc.z,
         mag.x,
         mag.y,
         mag.z,
         roll,
         pitch,
-        yaw
+        yaw,
+        imu.temperature,
+        altitude
     )
     .unwrap();
 
-    if serial.write(s.as_bytes()).ok().is_some() {
+    //a full record can be longer than SerialPort's buffer, a short one would break the CSV readers
+    if write_all(usb_dev, serial, s.as_bytes()).is_ok() {
         led_pin.toggle().ok();
     } else {
         led_pin.set_low().ok();
     }
 }
+
+//'t' runs the factory self-test for incoming QA
+fn self_test_requested<U: UsbBus>(serial: &mut SerialPort<U>) -> bool {
+    let mut buf = [0u8; 64];
+    match serial.read(&mut buf) {
+        Ok(count) => buf[..count].contains(&b't'),
+        Err(UsbError::WouldBlock) => false,
+        Err(e) => {
+            error!("serial read error: {}", e);
+            false
+        }
+    }
+}
+
+//one line per sensor, x/y/z response in LSB and the verdict, as '#' lines the CSV readers skip
+fn write_self_test<U: UsbBus, E: Debug>(
+    usb_dev: &mut UsbDevice<U>,
+    serial: &mut SerialPort<U>,
+    result: Result<SelfTestReport, E>,
+) {
+    let report = match result {
+        Ok(report) => report,
+        Err(e) => {
+            error!("self-test failed: {}", defmt::Debug2Format(&e));
+            return;
+        }
+    };
+
+    let mut lines = heapless::Vec::<heapless::String<128>, 3>::new();
+    for (name, axes) in [("gyro", &report.gyro), ("accel", &report.accel)] {
+        let mut s = heapless::String::new();
+        core::write!(&mut s, "# self-test {name}").unwrap();
+        for axis in axes {
+            core::write!(
+                &mut s,
+                ",{},{}",
+                axis.response,
+                if axis.passed { "pass" } else { "FAIL" }
+            )
+            .unwrap();
+        }
+        core::write!(&mut s, "\r\n").unwrap();
+        lines.push(s).unwrap();
+    }
+    let mut s = heapless::String::new();
+    core::write!(
+        &mut s,
+        "# self-test {}\r\n",
+        if report.passed() { "PASS" } else { "FAIL" }
+    )
+    .unwrap();
+    lines.push(s).unwrap();
+
+    //the whole report doesn't fit in SerialPort's buffer, so a line at a time
+    for line in &lines {
+        if let Err(e) = write_all(usb_dev, serial, line.as_bytes()) {
+            error!("self-test report write failed: {}", e);
+            return;
+        }
+    }
+}
+
+//SerialPort takes at most its 128 byte buffer per write, so keep polling until the host has
+//it all. gives up after WRITE_POLLS polls without any room, the host isn't reading
+fn write_all<U: UsbBus>(
+    usb_dev: &mut UsbDevice<U>,
+    serial: &mut SerialPort<U>,
+    mut bytes: &[u8],
+) -> Result<(), UsbError> {
+    let mut polls = 0;
+    while !bytes.is_empty() {
+        match serial.write(bytes) {
+            Ok(count) => {
+                bytes = &bytes[count..];
+                polls = 0;
+            }
+            Err(UsbError::WouldBlock) if polls < WRITE_POLLS => {
+                usb_dev.poll(&mut [&mut *serial]);
+                polls += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,522 @@
+//BMP280 barometer, on the same I2C bus as the ICM-20948
+//
+//compensation is the datasheet's fixed point version, temperature has to be read with every
+//pressure reading since the pressure compensation depends on it. AsyncBmp280 is the same
+//driver on embedded-hal-async, the register values and compensation are shared between them
+
+use crate::register::bmp280::{CALIB00, CONFIG, CTRL_MEAS, ID, PRESS_MSB, RESET, STATUS};
+#[cfg(feature = "blocking")]
+use embedded_hal::blocking::{delay::DelayMs, i2c};
+#[cfg(feature = "async")]
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+
+const BMP_ID: u8 = 0x58;
+//RESET value that triggers a power on reset
+const SOFT_RESET: u8 = 0xB6;
+//start-up after a reset
+const RESET_DELAY_MS: u32 = 2;
+//STATUS flags
+const STATUS_MEASURING: u8 = 0x08;
+const STATUS_IM_UPDATE: u8 = 0x01;
+//status polls, 1ms apart, before giving up
+const STATUS_POLLS: u32 = 100;
+//ADC output when the measurement was skipped
+const ADC_SKIPPED: i32 = 0x80000;
+
+//set by the SDO pin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BmpAddress {
+    //0x76
+    #[default]
+    SdoLow,
+    //0x77
+    SdoHigh,
+}
+
+impl BmpAddress {
+    #[must_use]
+    pub const fn addr(self) -> u8 {
+        match self {
+            Self::SdoLow => 0x76,
+            Self::SdoHigh => 0x77,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BmpError<E> {
+    I2c(E),
+    BadId,
+    //the NVM copy or a forced measurement didn't finish in time
+    Timeout,
+    //temperature or pressure oversampling is Skip, or the calibration is unusable
+    Skipped,
+}
+
+//osrs_t and osrs_p
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Oversampling {
+    Skip,
+    #[default]
+    X1,
+    X2,
+    X4,
+    X8,
+    X16,
+}
+
+impl Oversampling {
+    //samples taken per measurement
+    const fn samples(self) -> u32 {
+        match self {
+            Self::Skip => 0,
+            Self::X1 => 1,
+            Self::X2 => 2,
+            Self::X4 => 4,
+            Self::X8 => 8,
+            Self::X16 => 16,
+        }
+    }
+}
+
+//IIR filter coefficient
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IirFilter {
+    #[default]
+    Off,
+    X2,
+    X4,
+    X8,
+    X16,
+}
+
+//inactive time between measurements in normal mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Standby {
+    #[default]
+    Ms0_5,
+    Ms62_5,
+    Ms125,
+    Ms250,
+    Ms500,
+    Ms1000,
+    Ms2000,
+    Ms4000,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BmpMode {
+    #[default]
+    Sleep,
+    //one measurement per measure call, then back to sleep
+    Forced,
+    //continuous measurements separated by the standby time
+    Normal,
+}
+
+impl BmpMode {
+    //ctrl_meas mode value
+    const fn bits(self) -> u8 {
+        match self {
+            Self::Sleep => 0b00,
+            Self::Forced => 0b01,
+            Self::Normal => 0b11,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BmpConfig {
+    pub temperature_oversampling: Oversampling,
+    pub pressure_oversampling: Oversampling,
+    pub filter: IirFilter,
+    pub standby: Standby,
+    pub mode: BmpMode,
+}
+
+impl BmpConfig {
+    //worst case measurement time, datasheet appendix
+    #[must_use]
+    pub const fn measurement_time_ms(&self) -> u32 {
+        //1.25ms + 2.3ms per temperature sample + 2.3ms + 0.575ms per pressure sample, in us
+        let mut time_us = 1250 + 2300 * self.temperature_oversampling.samples();
+        if self.pressure_oversampling.samples() > 0 {
+            time_us += 2300 * self.pressure_oversampling.samples() + 575;
+        }
+        time_us.div_ceil(1000)
+    }
+
+    //CTRL_MEAS value
+    const fn ctrl_meas(self, mode: BmpMode) -> u8 {
+        (self.temperature_oversampling as u8) << 5
+            | (self.pressure_oversampling as u8) << 2
+            | mode.bits()
+    }
+
+    //CONFIG value
+    const fn config(self) -> u8 {
+        (self.standby as u8) << 5 | (self.filter as u8) << 2
+    }
+
+    //register writes that apply the config. CONFIG writes can be ignored outside sleep mode, so
+    //it goes to sleep first and only normal mode is restarted, measure starts forced ones
+    const fn writes(self) -> [(u8, u8); 3] {
+        let mode = match self.mode {
+            BmpMode::Normal => BmpMode::Normal,
+            BmpMode::Sleep | BmpMode::Forced => BmpMode::Sleep,
+        };
+        [
+            (CTRL_MEAS, self.ctrl_meas(BmpMode::Sleep)),
+            (CONFIG, self.config()),
+            (CTRL_MEAS, self.ctrl_meas(mode)),
+        ]
+    }
+
+    //CTRL_MEAS value that starts a measurement, None in normal mode where they run by themselves
+    const fn forced_start(self) -> Option<u8> {
+        match self.mode {
+            BmpMode::Normal => None,
+            BmpMode::Sleep | BmpMode::Forced => Some(self.ctrl_meas(BmpMode::Forced)),
+        }
+    }
+}
+
+//dig_T1..dig_P9 from the NVM
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BmpCalibration {
+    pub t1: u16,
+    pub t2: i16,
+    pub t3: i16,
+    pub p1: u16,
+    pub p2: i16,
+    pub p3: i16,
+    pub p4: i16,
+    pub p5: i16,
+    pub p6: i16,
+    pub p7: i16,
+    pub p8: i16,
+    pub p9: i16,
+}
+
+impl BmpCalibration {
+    //CALIB00..CALIB23
+    #[must_use]
+    pub fn from_bytes(buffer: &[u8; 24]) -> Self {
+        let unsigned = |n: usize| u16::from_le_bytes([buffer[n * 2], buffer[n * 2 + 1]]);
+        let signed = |n: usize| i16::from_le_bytes([buffer[n * 2], buffer[n * 2 + 1]]);
+        Self {
+            t1: unsigned(0),
+            t2: signed(1),
+            t3: signed(2),
+            p1: unsigned(3),
+            p2: signed(4),
+            p3: signed(5),
+            p4: signed(6),
+            p5: signed(7),
+            p6: signed(8),
+            p7: signed(9),
+            p8: signed(10),
+            p9: signed(11),
+        }
+    }
+
+    //t_fine, the fine temperature the pressure compensation needs
+    #[must_use]
+    pub fn t_fine(&self, adc_t: i32) -> i32 {
+        let t1 = i32::from(self.t1);
+        let var1 = (((adc_t >> 3) - (t1 << 1)) * i32::from(self.t2)) >> 11;
+        let var2 = (((((adc_t >> 4) - t1) * ((adc_t >> 4) - t1)) >> 12) * i32::from(self.t3)) >> 14;
+        var1 + var2
+    }
+
+    //hundredths of a degree C
+    #[must_use]
+    pub const fn temperature(t_fine: i32) -> i32 {
+        (t_fine * 5 + 128) >> 8
+    }
+
+    //Pa in Q24.8, None if the calibration would divide by zero
+    #[must_use]
+    pub fn pressure(&self, adc_p: i32, t_fine: i32) -> Option<u32> {
+        let mut var1 = i64::from(t_fine) - 128_000;
+        let mut var2 = var1 * var1 * i64::from(self.p6);
+        var2 += (var1 * i64::from(self.p5)) << 17;
+        var2 += i64::from(self.p4) << 35;
+        var1 = ((var1 * var1 * i64::from(self.p3)) >> 8) + ((var1 * i64::from(self.p2)) << 12);
+        var1 = (((1 << 47) + var1) * i64::from(self.p1)) >> 33;
+        if var1 == 0 {
+            return None;
+        }
+
+        let mut p = 1_048_576 - i64::from(adc_p);
+        p = (((p << 31) - var2) * 3125) / var1;
+        var1 = (i64::from(self.p9) * (p >> 13) * (p >> 13)) >> 25;
+        var2 = (i64::from(self.p8) * p) >> 19;
+        p = ((p + var1 + var2) >> 8) + (i64::from(self.p7) << 4);
+        u32::try_from(p).ok()
+    }
+
+    //PRESS_MSB..TEMP_XLSB into a sample
+    fn compensate<E>(&self, buffer: [u8; 6]) -> Result<BmpSample, BmpError<E>> {
+        let adc_p = decode_adc(&buffer[..3]);
+        let adc_t = decode_adc(&buffer[3..]);
+        if adc_t == ADC_SKIPPED || adc_p == ADC_SKIPPED {
+            return Err(BmpError::Skipped);
+        }
+
+        let t_fine = self.t_fine(adc_t);
+        let pressure = self.pressure(adc_p, t_fine).ok_or(BmpError::Skipped)?;
+
+        #[allow(clippy::cast_precision_loss)]
+        Ok(BmpSample {
+            temperature: Self::temperature(t_fine) as f32 / 100.0,
+            pressure: pressure as f32 / 256.0,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BmpSample {
+    //degrees C
+    pub temperature: f32,
+    //Pa
+    pub pressure: f32,
+}
+
+#[cfg(feature = "blocking")]
+pub struct Bmp280<I, E>
+where
+    I: i2c::Read<Error = E> + i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
+{
+    i2c: I,
+    address: u8,
+    calibration: BmpCalibration,
+    config: BmpConfig,
+}
+
+#[cfg(feature = "blocking")]
+impl<I, E> Bmp280<I, E>
+where
+    I: i2c::Read<Error = E> + i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
+{
+    pub fn new(i2c: I, address: BmpAddress) -> Self {
+        Self {
+            i2c,
+            address: address.addr(),
+            calibration: BmpCalibration::default(),
+            config: BmpConfig::default(),
+        }
+    }
+
+    //reset, load the calibration and apply config
+    pub fn startup<D: DelayMs<u32>>(
+        &mut self,
+        config: &BmpConfig,
+        delay: &mut D,
+    ) -> Result<(), BmpError<E>> {
+        if self.who_am_i()? != BMP_ID {
+            return Err(BmpError::BadId);
+        }
+
+        self.write_reg(RESET, SOFT_RESET)?;
+        delay.delay_ms(RESET_DELAY_MS);
+        //the calibration is copied out of the NVM after a reset
+        self.wait_status(STATUS_IM_UPDATE, delay)?;
+
+        let mut buffer = [0; 24];
+        self.read_regs(CALIB00, &mut buffer)?;
+        self.calibration = BmpCalibration::from_bytes(&buffer);
+
+        self.configure(config)
+    }
+
+    pub fn who_am_i(&mut self) -> Result<u8, BmpError<E>> {
+        self.read_reg(ID)
+    }
+
+    pub const fn calibration(&self) -> &BmpCalibration {
+        &self.calibration
+    }
+
+    pub const fn config(&self) -> &BmpConfig {
+        &self.config
+    }
+
+    pub fn configure(&mut self, config: &BmpConfig) -> Result<(), BmpError<E>> {
+        for (reg, value) in config.writes() {
+            self.write_reg(reg, value)?;
+        }
+
+        self.config = *config;
+        Ok(())
+    }
+
+    //take a forced measurement, or the latest normal mode one
+    pub fn measure<D: DelayMs<u32>>(&mut self, delay: &mut D) -> Result<BmpSample, BmpError<E>> {
+        if let Some(ctrl_meas) = self.config.forced_start() {
+            self.write_reg(CTRL_MEAS, ctrl_meas)?;
+            delay.delay_ms(self.config.measurement_time_ms());
+            self.wait_status(STATUS_MEASURING, delay)?;
+        }
+        self.read()
+    }
+
+    //latest result registers, without starting a measurement
+    pub fn read(&mut self) -> Result<BmpSample, BmpError<E>> {
+        let mut buffer = [0; 6];
+        self.read_regs(PRESS_MSB, &mut buffer)?;
+        self.calibration.compensate(buffer)
+    }
+
+    //wait for a STATUS flag to clear
+    fn wait_status<D: DelayMs<u32>>(&mut self, flag: u8, delay: &mut D) -> Result<(), BmpError<E>> {
+        for _ in 0..STATUS_POLLS {
+            if self.read_reg(STATUS)? & flag == 0 {
+                return Ok(());
+            }
+            delay.delay_ms(1);
+        }
+        Err(BmpError::Timeout)
+    }
+
+    fn read_reg(&mut self, reg: u8) -> Result<u8, BmpError<E>> {
+        let mut buffer = [0; 1];
+        self.read_regs(reg, &mut buffer)?;
+        Ok(buffer[0])
+    }
+
+    fn read_regs(&mut self, reg: u8, buffer: &mut [u8]) -> Result<(), BmpError<E>> {
+        self.i2c
+            .write_read(self.address, &[reg], buffer)
+            .map_err(BmpError::I2c)
+    }
+
+    fn write_reg(&mut self, reg: u8, value: u8) -> Result<(), BmpError<E>> {
+        self.i2c
+            .write(self.address, &[reg, value])
+            .map_err(BmpError::I2c)
+    }
+}
+
+#[cfg(feature = "async")]
+pub struct AsyncBmp280<I> {
+    i2c: I,
+    address: u8,
+    calibration: BmpCalibration,
+    config: BmpConfig,
+}
+
+#[cfg(feature = "async")]
+impl<I: I2c> AsyncBmp280<I> {
+    pub fn new(i2c: I, address: BmpAddress) -> Self {
+        Self {
+            i2c,
+            address: address.addr(),
+            calibration: BmpCalibration::default(),
+            config: BmpConfig::default(),
+        }
+    }
+
+    //reset, load the calibration and apply config
+    pub async fn startup<D: DelayNs>(
+        &mut self,
+        config: &BmpConfig,
+        delay: &mut D,
+    ) -> Result<(), BmpError<I::Error>> {
+        if self.who_am_i().await? != BMP_ID {
+            return Err(BmpError::BadId);
+        }
+
+        self.write_reg(RESET, SOFT_RESET).await?;
+        delay.delay_ms(RESET_DELAY_MS).await;
+        //the calibration is copied out of the NVM after a reset
+        self.wait_status(STATUS_IM_UPDATE, delay).await?;
+
+        let mut buffer = [0; 24];
+        self.read_regs(CALIB00, &mut buffer).await?;
+        self.calibration = BmpCalibration::from_bytes(&buffer);
+
+        self.configure(config).await
+    }
+
+    pub async fn who_am_i(&mut self) -> Result<u8, BmpError<I::Error>> {
+        self.read_reg(ID).await
+    }
+
+    pub const fn calibration(&self) -> &BmpCalibration {
+        &self.calibration
+    }
+
+    pub const fn config(&self) -> &BmpConfig {
+        &self.config
+    }
+
+    pub async fn configure(&mut self, config: &BmpConfig) -> Result<(), BmpError<I::Error>> {
+        for (reg, value) in config.writes() {
+            self.write_reg(reg, value).await?;
+        }
+
+        self.config = *config;
+        Ok(())
+    }
+
+    //take a forced measurement, or the latest normal mode one
+    pub async fn measure<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<BmpSample, BmpError<I::Error>> {
+        if let Some(ctrl_meas) = self.config.forced_start() {
+            self.write_reg(CTRL_MEAS, ctrl_meas).await?;
+            delay.delay_ms(self.config.measurement_time_ms()).await;
+            self.wait_status(STATUS_MEASURING, delay).await?;
+        }
+        self.read().await
+    }
+
+    //latest result registers, without starting a measurement
+    pub async fn read(&mut self) -> Result<BmpSample, BmpError<I::Error>> {
+        let mut buffer = [0; 6];
+        self.read_regs(PRESS_MSB, &mut buffer).await?;
+        self.calibration.compensate(buffer)
+    }
+
+    //wait for a STATUS flag to clear
+    async fn wait_status<D: DelayNs>(
+        &mut self,
+        flag: u8,
+        delay: &mut D,
+    ) -> Result<(), BmpError<I::Error>> {
+        for _ in 0..STATUS_POLLS {
+            if self.read_reg(STATUS).await? & flag == 0 {
+                return Ok(());
+            }
+            delay.delay_ms(1).await;
+        }
+        Err(BmpError::Timeout)
+    }
+
+    async fn read_reg(&mut self, reg: u8) -> Result<u8, BmpError<I::Error>> {
+        let mut buffer = [0; 1];
+        self.read_regs(reg, &mut buffer).await?;
+        Ok(buffer[0])
+    }
+
+    async fn read_regs(&mut self, reg: u8, buffer: &mut [u8]) -> Result<(), BmpError<I::Error>> {
+        self.i2c
+            .write_read(self.address, &[reg], buffer)
+            .await
+            .map_err(BmpError::I2c)
+    }
+
+    async fn write_reg(&mut self, reg: u8, value: u8) -> Result<(), BmpError<I::Error>> {
+        self.i2c
+            .write(self.address, &[reg, value])
+            .await
+            .map_err(BmpError::I2c)
+    }
+}
+
+//20 bit ADC value from MSB, LSB, XLSB
+fn decode_adc(buffer: &[u8]) -> i32 {
+    i32::from(buffer[0]) << 12 | i32::from(buffer[1]) << 4 | i32::from(buffer[2]) >> 4
+}
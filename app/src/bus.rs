@@ -0,0 +1,93 @@
+//I2C bus proxies so the ICM-20948 and BMP280 drivers can share one bus
+//
+//each proxy borrows the bus for a single transaction, so the drivers can be used in any order.
+//RefCellDevice is for sharing within one context, MutexDevice when the bus is also used
+//from an interrupt handler
+
+use core::cell::RefCell;
+use cortex_m::interrupt::{self, Mutex};
+use embedded_hal::blocking::i2c;
+
+pub struct RefCellDevice<'a, I> {
+    bus: &'a RefCell<I>,
+}
+
+impl<'a, I> RefCellDevice<'a, I> {
+    pub const fn new(bus: &'a RefCell<I>) -> Self {
+        Self { bus }
+    }
+}
+
+impl<I: i2c::Read> i2c::Read for RefCellDevice<'_, I> {
+    type Error = I::Error;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        self.bus.borrow_mut().read(address, buffer)
+    }
+}
+
+impl<I: i2c::Write> i2c::Write for RefCellDevice<'_, I> {
+    type Error = I::Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.bus.borrow_mut().write(address, bytes)
+    }
+}
+
+impl<I: i2c::WriteRead> i2c::WriteRead for RefCellDevice<'_, I> {
+    type Error = I::Error;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        self.bus.borrow_mut().write_read(address, bytes, buffer)
+    }
+}
+
+//each transaction runs with interrupts disabled
+pub struct MutexDevice<'a, I> {
+    bus: &'a Mutex<RefCell<I>>,
+}
+
+impl<'a, I> MutexDevice<'a, I> {
+    pub const fn new(bus: &'a Mutex<RefCell<I>>) -> Self {
+        Self { bus }
+    }
+}
+
+impl<I: i2c::Read> i2c::Read for MutexDevice<'_, I> {
+    type Error = I::Error;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        interrupt::free(|cs| self.bus.borrow(cs).borrow_mut().read(address, buffer))
+    }
+}
+
+impl<I: i2c::Write> i2c::Write for MutexDevice<'_, I> {
+    type Error = I::Error;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+        interrupt::free(|cs| self.bus.borrow(cs).borrow_mut().write(address, bytes))
+    }
+}
+
+impl<I: i2c::WriteRead> i2c::WriteRead for MutexDevice<'_, I> {
+    type Error = I::Error;
+
+    fn write_read(
+        &mut self,
+        address: u8,
+        bytes: &[u8],
+        buffer: &mut [u8],
+    ) -> Result<(), Self::Error> {
+        interrupt::free(|cs| {
+            self.bus
+                .borrow(cs)
+                .borrow_mut()
+                .write_read(address, bytes, buffer)
+        })
+    }
+}
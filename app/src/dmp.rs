@@ -0,0 +1,531 @@
+//ICM-20948 Digital Motion Processor
+//
+//the DMP image isn't part of this crate, it's InvenSense's icm20948_img.dmp3a.h and is passed
+//in as bytes. the DMP runs off accel and gyro at 56.25Hz, +-4g and +-2000dps, and writes
+//variable length packets into the FIFO in place of the frames fifo_configure sets up.
+//quaternions are in the chip's own axes, the mounting rotation isn't applied
+
+use crate::{
+    i2c_master::{SLV_EN, SLV_RNW},
+    register::{
+        ak09916, dmp, UserCtrl, FIFO_R_W, HW_FIX_DISABLE, I2C_MST_ODR_CONFIG, I2C_SLV0_ADDR,
+        I2C_SLV0_CTRL, I2C_SLV0_REG, I2C_SLV1_ADDR, I2C_SLV1_CTRL, I2C_SLV1_DO, I2C_SLV1_REG,
+        MEM_BANK_SEL, MEM_R_W, MEM_START_ADDR, PRGM_START_ADDRH, SINGLE_FIFO_PRIORITY_SEL,
+        TIMEBASE_CORRECTION_PLL,
+    },
+    AccelRange, FifoConfig, GyroRange, Imc20948, ImcError, MagAccess, Transport, MAG_ADDR,
+};
+use nalgebra::{ComplexField, Quaternion, UnitQuaternion};
+
+//the image is loaded here and the DMP starts from PRGM_START_ADDR
+const LOAD_START: u16 = 0x90;
+const PRGM_START: u16 = 0x1000;
+const MEM_BANK_SIZE: u16 = 256;
+//MEM_R_W bursts
+const MEM_CHUNK: u16 = 16;
+
+//accel and gyro run at 1125Hz / (19 + 1)
+const SAMPLE_RATE_DIVIDER: u8 = 19;
+//DMP scaling for +-4g and +-2000dps
+const ACC_SCALE: u32 = 0x0400_0000;
+const ACC_SCALE2: u32 = 0x0004_0000;
+const GYRO_FULLSCALE: u32 = 0x1000_0000;
+//accel calibration gains for 56Hz
+const ACCEL_ONLY_GAIN: u32 = 0x00E8_BA2E;
+const ACCEL_ALPHA_VAR: u32 = 0x3D27_D27D;
+const ACCEL_A_VAR: u32 = 0x02D8_2D83;
+//ms the compass data lags by
+const CPASS_TIME_BUFFER: u16 = 0x0045;
+//FIFO fill in bytes that raises the DMP watermark
+const FIFO_WATERMARK: u16 = 800;
+//SINGLE_FIFO_PRIORITY_SEL and HW_FIX_DISABLE values the DMP image expects
+const FIFO_PRIORITY: u8 = 0xE4;
+const HW_FIX: u8 = 0x48;
+
+//GYRO_SF = MAGIC * 2^FS_SEL * (divider + 1) / (1270 +- pll trim) / MAGIC_SCALE
+const GYRO_SF_MAGIC: i64 = 264_446_880_937_391;
+const GYRO_SF_MAGIC_SCALE: i64 = 100_000;
+//the InvenSense/SparkFun reference uses 4 for +-2000dps, not the GYRO_FS_SEL value of 3
+const GYRO_SF_LEVEL: u32 = 4;
+
+//AK09916 axes to ICM-20948 axes, +-1 in the DMP's fixed point
+const CPASS_PLUS: u32 = 0x0999_9999;
+const CPASS_MINUS: u32 = 0xF666_6667;
+//identity body to sensor matrix
+const B2S_ONE: u32 = 0x4000_0000;
+
+//SLV0 reads RSV2..ST2 byte swapped and grouped in pairs, SLV1 triggers the next measurement
+const SLV_BYTE_SW: u8 = 0x40;
+const SLV_GRP: u8 = 0x10;
+const MAG_READ_LEN: u8 = 10;
+const MAG_SINGLE_MEASUREMENT: u8 = 0x01;
+//aux I2C master at 1100Hz / 2^4
+const I2C_MST_ODR: u8 = 0x04;
+
+//DATA_OUT_CTL1 and packet header bits
+const HEADER_ACCEL: u16 = 0x8000;
+const HEADER_GYRO: u16 = 0x4000;
+const HEADER_CPASS: u16 = 0x2000;
+const HEADER_ALS: u16 = 0x1000;
+const HEADER_QUAT6: u16 = 0x0800;
+const HEADER_QUAT9: u16 = 0x0400;
+const HEADER_PQUAT6: u16 = 0x0200;
+const HEADER_GEOMAG: u16 = 0x0100;
+const HEADER_PRESSURE: u16 = 0x0080;
+const HEADER_GYRO_CALIBR: u16 = 0x0040;
+const HEADER_CPASS_CALIBR: u16 = 0x0020;
+const HEADER_STEP_DETECTOR: u16 = 0x0010;
+const HEADER_HEADER2: u16 = 0x0008;
+
+//DATA_OUT_CTL2 and second header bits
+const HEADER2_ACCEL_ACCURACY: u16 = 0x4000;
+const HEADER2_GYRO_ACCURACY: u16 = 0x2000;
+const HEADER2_CPASS_ACCURACY: u16 = 0x1000;
+const HEADER2_FSYNC: u16 = 0x0800;
+const HEADER2_PICKUP: u16 = 0x0400;
+const HEADER2_ACT_RECOG: u16 = 0x0080;
+const HEADER2_SECONDARY_ON_OFF: u16 = 0x0040;
+
+//MOTION_EVENT_CTL bits
+const MOTION_PEDOMETER: u16 = 0x4000;
+const MOTION_ACCEL_CAL: u16 = 0x0200;
+const MOTION_GYRO_CAL: u16 = 0x0100;
+const MOTION_CPASS_CAL: u16 = 0x0080;
+const MOTION_GEOMAG: u16 = 0x0008;
+
+//DATA_RDY_STATUS bits, the sensors the DMP waits on
+const READY_GYRO: u16 = 0x0001;
+const READY_ACCEL: u16 = 0x0002;
+const READY_SECONDARY: u16 = 0x0008;
+
+//bytes per packet field, in packet order
+const HEADER_FIELDS: [(u16, usize); 12] = [
+    (HEADER_ACCEL, 6),
+    //gyro then gyro bias
+    (HEADER_GYRO, 12),
+    (HEADER_CPASS, 6),
+    (HEADER_ALS, 8),
+    (HEADER_QUAT6, 12),
+    //quaternion then heading accuracy
+    (HEADER_QUAT9, 14),
+    (HEADER_PQUAT6, 6),
+    (HEADER_GEOMAG, 14),
+    (HEADER_PRESSURE, 6),
+    (HEADER_GYRO_CALIBR, 12),
+    (HEADER_CPASS_CALIBR, 12),
+    (HEADER_STEP_DETECTOR, 4),
+];
+const HEADER2_FIELDS: [(u16, usize); 7] = [
+    (HEADER2_ACCEL_ACCURACY, 2),
+    (HEADER2_GYRO_ACCURACY, 2),
+    (HEADER2_CPASS_ACCURACY, 2),
+    (HEADER2_FSYNC, 2),
+    (HEADER2_PICKUP, 2),
+    (HEADER2_ACT_RECOG, 6),
+    (HEADER2_SECONDARY_ON_OFF, 2),
+];
+//bits a valid header can carry, anything else means the packet boundaries are lost
+const HEADER_VALID: u16 = HEADER_ACCEL
+    | HEADER_GYRO
+    | HEADER_CPASS
+    | HEADER_ALS
+    | HEADER_QUAT6
+    | HEADER_QUAT9
+    | HEADER_PQUAT6
+    | HEADER_GEOMAG
+    | HEADER_PRESSURE
+    | HEADER_GYRO_CALIBR
+    | HEADER_CPASS_CALIBR
+    | HEADER_STEP_DETECTOR
+    | HEADER_HEADER2;
+const HEADER2_VALID: u16 = HEADER2_ACCEL_ACCURACY
+    | HEADER2_GYRO_ACCURACY
+    | HEADER2_CPASS_ACCURACY
+    | HEADER2_FSYNC
+    | HEADER2_PICKUP
+    | HEADER2_ACT_RECOG
+    | HEADER2_SECONDARY_ON_OFF;
+const HEADER_LEN: usize = 2;
+//ODR counter after every packet
+const FOOTER_LEN: usize = 2;
+//every field present
+const MAX_PACKET_LEN: usize = 136;
+//FIFO count polls waiting for the rest of a packet
+const PACKET_POLLS: u32 = 1000;
+
+//Q30 fixed point
+const Q30: f32 = 1_073_741_824.0;
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DmpConfig {
+    //6-axis accel and gyro quaternion
+    pub game_rotation_vector: bool,
+    //6-axis accel and mag quaternion, needs MagAccess::AuxMaster
+    pub geomagnetic_rotation_vector: bool,
+    //dmp_step_count
+    pub step_counter: bool,
+    //packets every odr_divider + 1 DMP samples
+    pub odr_divider: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DmpPacket {
+    pub game_rotation: Option<UnitQuaternion<f32>>,
+    pub geomagnetic_rotation: Option<UnitQuaternion<f32>>,
+    //DMP heading accuracy estimate for geomagnetic_rotation
+    pub geomagnetic_accuracy: Option<u16>,
+    //DMP calibration state, 0 (unreliable) to 3 (calibrated)
+    pub accel_accuracy: Option<u16>,
+    pub gyro_accuracy: Option<u16>,
+    pub mag_accuracy: Option<u16>,
+}
+
+impl DmpPacket {
+    //bytes in the packet with these headers, header and footer included
+    #[must_use]
+    pub fn packet_len(header: u16, header2: u16) -> usize {
+        let mut len = HEADER_LEN + FOOTER_LEN;
+        if header & HEADER_HEADER2 != 0 {
+            len += HEADER_LEN;
+            len += field_len(&HEADER2_FIELDS, header2);
+        }
+        len + field_len(&HEADER_FIELDS, header)
+    }
+
+    //decode one whole packet
+    #[must_use]
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let header = u16::from_be_bytes([*data.first()?, *data.get(1)?]);
+        let has_header2 = header & HEADER_HEADER2 != 0;
+        let header2 = if has_header2 {
+            u16::from_be_bytes([*data.get(2)?, *data.get(3)?])
+        } else {
+            0
+        };
+        if data.len() < Self::packet_len(header, header2) {
+            return None;
+        }
+        let headers_len = if has_header2 {
+            2 * HEADER_LEN
+        } else {
+            HEADER_LEN
+        };
+        let mut data = &data[headers_len..];
+
+        let mut packet = Self::default();
+        for (bit, len) in HEADER_FIELDS {
+            if header & bit == 0 {
+                continue;
+            }
+            let field = &data[..len];
+            match bit {
+                HEADER_QUAT6 => packet.game_rotation = Some(decode_quaternion(field)),
+                HEADER_GEOMAG => {
+                    packet.geomagnetic_rotation = Some(decode_quaternion(field));
+                    packet.geomagnetic_accuracy = Some(u16::from_be_bytes([field[12], field[13]]));
+                }
+                _ => {}
+            }
+            data = &data[len..];
+        }
+        for (bit, len) in HEADER2_FIELDS {
+            if header2 & bit == 0 {
+                continue;
+            }
+            let accuracy = Some(u16::from_be_bytes([data[0], data[1]]));
+            match bit {
+                HEADER2_ACCEL_ACCURACY => packet.accel_accuracy = accuracy,
+                HEADER2_GYRO_ACCURACY => packet.gyro_accuracy = accuracy,
+                HEADER2_CPASS_ACCURACY => packet.mag_accuracy = accuracy,
+                _ => {}
+            }
+            data = &data[len..];
+        }
+        Some(packet)
+    }
+}
+
+impl<T, E> Imc20948<T, E>
+where
+    T: Transport<Error = E>,
+{
+    //upload the DMP image and read it back, after startup and before dmp_configure
+    pub fn dmp_load(&mut self, image: &[u8]) -> Result<(), ImcError<E>> {
+        //DMP addresses are 16 bit
+        if u16::try_from(usize::from(LOAD_START) + image.len()).is_err() {
+            return Err(ImcError::DmpImageSize);
+        }
+        //the DMP memory is only reachable with the chip awake and out of low power
+        self.leave_low_power()?;
+        self.imu_wake()?;
+
+        self.dmp_write_mem(LOAD_START, image)?;
+
+        let mut buffer = [0; MEM_CHUNK as usize];
+        for (n, expected) in (0..).zip(image.chunks(buffer.len())) {
+            let address = LOAD_START + n * MEM_CHUNK;
+            let actual = &mut buffer[..expected.len()];
+            self.dmp_read_mem(address, actual)?;
+            if actual != expected {
+                return Err(ImcError::DmpVerify { address });
+            }
+        }
+
+        self.write_config(PRGM_START_ADDRH, PRGM_START.to_be_bytes()[0])?;
+        self.write_config(PRGM_START_ADDRH.offset(1), PRGM_START.to_be_bytes()[1])
+    }
+
+    //set the chip up the way the DMP image expects and start it
+    pub fn dmp_configure(&mut self, config: &DmpConfig) -> Result<(), ImcError<E>> {
+        if config.geomagnetic_rotation_vector && self.mag_access != MagAccess::AuxMaster {
+            return Err(ImcError::WrongMagAccess);
+        }
+
+        self.modify(|r: UserCtrl| r.with_dmp_en(false))?;
+        //the DMP fills the FIFO itself
+        self.fifo_configure(FifoConfig::default())?;
+
+        self.set_gyro_range(GyroRange::Dps2000)?;
+        self.set_accel_range(AccelRange::G4)?;
+        self.set_gyro_sample_rate_divider(SAMPLE_RATE_DIVIDER)?;
+        self.set_accel_sample_rate_divider(u16::from(SAMPLE_RATE_DIVIDER))?;
+        self.write_config(SINGLE_FIFO_PRIORITY_SEL, FIFO_PRIORITY)?;
+        self.write_config(HW_FIX_DISABLE, HW_FIX)?;
+
+        self.dmp_write_u32(dmp::ACC_SCALE, ACC_SCALE)?;
+        self.dmp_write_u32(dmp::ACC_SCALE2, ACC_SCALE2)?;
+        self.dmp_write_u32(dmp::GYRO_FULLSCALE, GYRO_FULLSCALE)?;
+        let gyro_sf = self.dmp_gyro_sf()?;
+        self.dmp_write_u32(dmp::GYRO_SF, gyro_sf)?;
+        self.dmp_write_u32(dmp::ACCEL_ONLY_GAIN, ACCEL_ONLY_GAIN)?;
+        self.dmp_write_u32(dmp::ACCEL_ALPHA_VAR, ACCEL_ALPHA_VAR)?;
+        self.dmp_write_u32(dmp::ACCEL_A_VAR, ACCEL_A_VAR)?;
+        self.dmp_write_u16(dmp::ACCEL_CAL_RATE, 0)?;
+        self.dmp_write_u16(dmp::FIFO_WATERMARK, FIFO_WATERMARK)?;
+        self.dmp_write_u16(dmp::BAC_RATE, 0)?;
+        self.dmp_write_u16(dmp::B2S_RATE, 0)?;
+
+        for (address, value) in [
+            (dmp::B2S_MTX_00, B2S_ONE),
+            (dmp::B2S_MTX_01, 0),
+            (dmp::B2S_MTX_02, 0),
+            (dmp::B2S_MTX_10, 0),
+            (dmp::B2S_MTX_11, B2S_ONE),
+            (dmp::B2S_MTX_12, 0),
+            (dmp::B2S_MTX_20, 0),
+            (dmp::B2S_MTX_21, 0),
+            (dmp::B2S_MTX_22, B2S_ONE),
+        ] {
+            self.dmp_write_u32(address, value)?;
+        }
+
+        let mut control = 0;
+        let mut control2 = 0;
+        let mut motion = 0;
+        let mut ready = 0;
+        if config.game_rotation_vector {
+            control |= HEADER_QUAT6;
+            control2 |= HEADER2_ACCEL_ACCURACY | HEADER2_GYRO_ACCURACY;
+            motion |= MOTION_ACCEL_CAL | MOTION_GYRO_CAL;
+            ready |= READY_ACCEL | READY_GYRO;
+            self.dmp_write_u16(dmp::ODR_QUAT6, config.odr_divider)?;
+            self.dmp_write_u16(dmp::ODR_CNTR_QUAT6, 0)?;
+        }
+        if config.geomagnetic_rotation_vector {
+            self.dmp_start_mag()?;
+            control |= HEADER_GEOMAG;
+            control2 |= HEADER2_ACCEL_ACCURACY | HEADER2_CPASS_ACCURACY;
+            motion |= MOTION_ACCEL_CAL | MOTION_CPASS_CAL | MOTION_GEOMAG;
+            ready |= READY_ACCEL | READY_SECONDARY;
+            self.dmp_write_u16(dmp::ODR_GEOMAG, config.odr_divider)?;
+            self.dmp_write_u16(dmp::ODR_CNTR_GEOMAG, 0)?;
+        }
+        if config.step_counter {
+            motion |= MOTION_PEDOMETER;
+            ready |= READY_ACCEL;
+        }
+        if control2 != 0 {
+            control |= HEADER_HEADER2;
+        }
+        self.dmp_write_u16(dmp::DATA_OUT_CTL1, control)?;
+        self.dmp_write_u16(dmp::DATA_OUT_CTL2, control2)?;
+        self.dmp_write_u16(dmp::DATA_INTR_CTL, control)?;
+        self.dmp_write_u16(dmp::MOTION_EVENT_CTL, motion)?;
+        self.dmp_write_u16(dmp::DATA_RDY_STATUS, ready)?;
+
+        self.fifo_reset()?;
+        //DMP_RST self clears
+        self.modify(|r: UserCtrl| r.with_dmp_rst(true))?;
+        self.modify(|r: UserCtrl| r.with_dmp_en(true).with_fifo_en(true))
+    }
+
+    //next packet from the FIFO, None if there isn't one waiting or the FIFO was reset to resync
+    pub fn dmp_read(&mut self) -> Result<Option<DmpPacket>, ImcError<E>> {
+        if self.fifo_overflow()? {
+            //the packet boundaries are lost, start again
+            self.fifo_reset()?;
+            return Ok(None);
+        }
+        if usize::from(self.fifo_count()?) < HEADER_LEN {
+            return Ok(None);
+        }
+
+        let mut buffer = [0; MAX_PACKET_LEN];
+        self.fifo_read_bytes(&mut buffer[..HEADER_LEN])?;
+        let header = u16::from_be_bytes([buffer[0], buffer[1]]);
+        let mut read = HEADER_LEN;
+        if header & !HEADER_VALID != 0 {
+            self.fifo_reset()?;
+            return Ok(None);
+        }
+
+        let mut header2 = 0;
+        if header & HEADER_HEADER2 != 0 {
+            self.fifo_read_bytes(&mut buffer[read..read + HEADER_LEN])?;
+            header2 = u16::from_be_bytes([buffer[read], buffer[read + 1]]);
+            read += HEADER_LEN;
+            if header2 & !HEADER2_VALID != 0 {
+                self.fifo_reset()?;
+                return Ok(None);
+            }
+        }
+
+        let len = DmpPacket::packet_len(header, header2);
+        self.fifo_read_bytes(&mut buffer[read..len])?;
+        Ok(DmpPacket::parse(&buffer[..len]))
+    }
+
+    //pedometer steps since the DMP was configured
+    pub fn dmp_step_count(&mut self) -> Result<u32, ImcError<E>> {
+        let mut buffer = [0; 4];
+        self.dmp_read_mem(dmp::PEDSTD_STEPCTR, &mut buffer)?;
+        Ok(u32::from_be_bytes(buffer))
+    }
+
+    //bytes straight from FIFO_R_W, waiting for the DMP to finish writing them
+    fn fifo_read_bytes(&mut self, buffer: &mut [u8]) -> Result<(), ImcError<E>> {
+        for _ in 0..PACKET_POLLS {
+            if usize::from(self.fifo_count()?) >= buffer.len() {
+                return self.read_regs(FIFO_R_W, buffer);
+            }
+        }
+        Err(ImcError::Timeout)
+    }
+
+    //SLV0 and SLV1 the way the DMP's compass layer expects them, mag_read no longer applies
+    fn dmp_start_mag(&mut self) -> Result<(), ImcError<E>> {
+        self.write_config(I2C_SLV0_ADDR, SLV_RNW | MAG_ADDR)?;
+        self.write_config(I2C_SLV0_REG, ak09916::RSV2)?;
+        self.write_config(I2C_SLV0_CTRL, SLV_EN | SLV_BYTE_SW | SLV_GRP | MAG_READ_LEN)?;
+
+        self.write_config(I2C_SLV1_ADDR, MAG_ADDR)?;
+        self.write_config(I2C_SLV1_REG, ak09916::CNTL2)?;
+        self.write_config(I2C_SLV1_DO, MAG_SINGLE_MEASUREMENT)?;
+        self.write_config(I2C_SLV1_CTRL, SLV_EN | 1)?;
+
+        self.write_config(I2C_MST_ODR_CONFIG, I2C_MST_ODR)?;
+
+        for (address, value) in [
+            (dmp::CPASS_MTX_00, CPASS_PLUS),
+            (dmp::CPASS_MTX_01, 0),
+            (dmp::CPASS_MTX_02, 0),
+            (dmp::CPASS_MTX_10, 0),
+            (dmp::CPASS_MTX_11, CPASS_MINUS),
+            (dmp::CPASS_MTX_12, 0),
+            (dmp::CPASS_MTX_20, 0),
+            (dmp::CPASS_MTX_21, 0),
+            (dmp::CPASS_MTX_22, CPASS_MINUS),
+        ] {
+            self.dmp_write_u32(address, value)?;
+        }
+        self.dmp_write_u16(dmp::CPASS_TIME_BUFFER, CPASS_TIME_BUFFER)
+    }
+
+    //gyro scale factor corrected for this part's PLL trim
+    fn dmp_gyro_sf(&mut self) -> Result<u32, ImcError<E>> {
+        let pll = self.read_reg(TIMEBASE_CORRECTION_PLL)?;
+        let trim = i64::from(pll & 0x7F);
+        let base = if pll & 0x80 != 0 {
+            1270 - trim
+        } else {
+            1270 + trim
+        };
+
+        let sf = GYRO_SF_MAGIC * (1 << GYRO_SF_LEVEL) * (i64::from(SAMPLE_RATE_DIVIDER) + 1)
+            / base
+            / GYRO_SF_MAGIC_SCALE;
+        Ok(u32::try_from(sf.min(i64::from(i32::MAX))).unwrap_or(0))
+    }
+
+    fn dmp_write_u16(&mut self, address: u16, value: u16) -> Result<(), ImcError<E>> {
+        self.dmp_write_mem(address, &value.to_be_bytes())
+    }
+
+    fn dmp_write_u32(&mut self, address: u16, value: u32) -> Result<(), ImcError<E>> {
+        self.dmp_write_mem(address, &value.to_be_bytes())
+    }
+
+    //chunks can't cross a memory bank
+    fn dmp_write_mem(&mut self, mut address: u16, mut data: &[u8]) -> Result<(), ImcError<E>> {
+        while !data.is_empty() {
+            let len = dmp_chunk_len(address, data.len());
+            self.dmp_set_address(address)?;
+            let (chunk, rest) = data.split_at(usize::from(len));
+            self.write_regs(MEM_R_W, chunk)?;
+            data = rest;
+            address += len;
+        }
+        Ok(())
+    }
+
+    fn dmp_read_mem(&mut self, mut address: u16, mut buffer: &mut [u8]) -> Result<(), ImcError<E>> {
+        while !buffer.is_empty() {
+            let len = dmp_chunk_len(address, buffer.len());
+            self.dmp_set_address(address)?;
+            let (chunk, rest) = buffer.split_at_mut(usize::from(len));
+            self.read_regs(MEM_R_W, chunk)?;
+            buffer = rest;
+            address += len;
+        }
+        Ok(())
+    }
+
+    fn dmp_set_address(&mut self, address: u16) -> Result<(), ImcError<E>> {
+        let [bank, start] = address.to_be_bytes();
+        self.write_reg(MEM_BANK_SEL, bank)?;
+        self.write_reg(MEM_START_ADDR, start)
+    }
+}
+
+//bytes that can go in one MEM_R_W burst from address
+fn dmp_chunk_len(address: u16, remaining: usize) -> u16 {
+    let max = MEM_CHUNK.min(MEM_BANK_SIZE - address % MEM_BANK_SIZE);
+    u16::try_from(remaining).map_or(max, |remaining| remaining.min(max))
+}
+
+fn field_len(fields: &[(u16, usize)], header: u16) -> usize {
+    fields
+        .iter()
+        .filter(|(bit, _)| header & bit != 0)
+        .map(|(_, len)| len)
+        .sum()
+}
+
+//three Q30 components, w is implied by the unit norm
+fn decode_quaternion(data: &[u8]) -> UnitQuaternion<f32> {
+    let component = |n: usize| {
+        let bytes = [
+            data[n * 4],
+            data[n * 4 + 1],
+            data[n * 4 + 2],
+            data[n * 4 + 3],
+        ];
+        #[allow(clippy::cast_precision_loss)]
+        let value = i32::from_be_bytes(bytes) as f32 / Q30;
+        value
+    };
+    let (x, y, z) = (component(0), component(1), component(2));
+    let w = ComplexField::sqrt((1.0 - x * x - y * y - z * z).max(0.0));
+    UnitQuaternion::from_quaternion(Quaternion::new(w, x, y, z))
+}
@@ -0,0 +1,235 @@
+//ICM-20948 hardware FIFO
+//
+//frames are written in register order: accel, gyro, temp, then the mag from EXT_SLV_SENS_DATA.
+//the FIFO carries no timestamps, they're rebuilt from the frame count and the ODR
+
+use crate::{
+    decode_accel, decode_gyro, decode_mag, decode_temperature,
+    register::{
+        FifoEn2, UserCtrl, FIFO_COUNTH, FIFO_EN_1, FIFO_MODE, FIFO_RST, FIFO_R_W, INT_STATUS_2,
+    },
+    Imc20948, ImcError, MagAccess, MagSample, Transport,
+};
+use nalgebra::Vector3;
+
+const ACCEL_LEN: usize = 6;
+const GYRO_LEN: usize = 6;
+const TEMP_LEN: usize = 2;
+const MAG_LEN: usize = 9;
+//largest burst read of FIFO_R_W
+const READ_CHUNK: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FifoMode {
+    //oldest data is overwritten when full
+    #[default]
+    Stream,
+    //writes stop when full
+    Snapshot,
+}
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FifoConfig {
+    pub accel: bool,
+    pub gyro: bool,
+    pub temperature: bool,
+    //needs MagAccess::AuxMaster, SLV0 is what fills the FIFO
+    pub mag: bool,
+    pub mode: FifoMode,
+}
+
+impl FifoConfig {
+    //bytes per frame
+    #[must_use]
+    pub const fn frame_len(&self) -> usize {
+        let mut len = 0;
+        if self.accel {
+            len += ACCEL_LEN;
+        }
+        if self.gyro {
+            len += GYRO_LEN;
+        }
+        if self.temperature {
+            len += TEMP_LEN;
+        }
+        if self.mag {
+            len += MAG_LEN;
+        }
+        len
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FifoFrame {
+    //microseconds since the FIFO was configured or last reset
+    pub timestamp_us: u64,
+    pub acc: Option<Vector3<f32>>,
+    pub gyro: Option<Vector3<f32>>,
+    pub temperature: Option<f32>,
+    pub mag: Option<MagSample>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FifoRead {
+    //frames written to the output slice
+    pub frames: usize,
+    //the FIFO overflowed since the last read. in Stream mode it was reset, samples have been lost
+    //and the timestamps of the next frames start again from 0. in Snapshot mode it's full and
+    //the retained frames are still read, fifo_reset to capture again
+    pub overflow: bool,
+}
+
+impl<T, E> Imc20948<T, E>
+where
+    T: Transport<Error = E>,
+{
+    pub fn fifo_configure(&mut self, config: FifoConfig) -> Result<(), ImcError<E>> {
+        if config.mag && self.mag_access != MagAccess::AuxMaster {
+            return Err(ImcError::WrongMagAccess);
+        }
+
+        //stop filling while the frame layout changes
+        self.modify(|r: UserCtrl| r.with_fifo_en(false))?;
+
+        //SLV_0_FIFO_EN
+        self.write_config(FIFO_EN_1, u8::from(config.mag))?;
+        self.write(
+            FifoEn2::default()
+                .with_accel_fifo_en(config.accel)
+                .with_gyro_x_fifo_en(config.gyro)
+                .with_gyro_y_fifo_en(config.gyro)
+                .with_gyro_z_fifo_en(config.gyro)
+                .with_temp_fifo_en(config.temperature),
+        )?;
+        let mode = match config.mode {
+            FifoMode::Stream => 0x00,
+            FifoMode::Snapshot => 0x1F,
+        };
+        self.write_config(FIFO_MODE, mode)?;
+
+        self.fifo = config;
+        self.fifo_reset()?;
+
+        if config.frame_len() > 0 {
+            self.modify(|r: UserCtrl| r.with_fifo_en(true))?;
+        }
+        Ok(())
+    }
+
+    pub const fn fifo_config(&self) -> &FifoConfig {
+        &self.fifo
+    }
+
+    //drop everything in the FIFO, frame timestamps start again from 0
+    pub fn fifo_reset(&mut self) -> Result<(), ImcError<E>> {
+        //the dropped frames are lost from the count, so the timestamps can't carry on
+        self.fifo_frames = 0;
+        //FIFO_RESET has to be asserted then deasserted
+        self.write_reg(FIFO_RST, 0x1F)?;
+        self.write_reg(FIFO_RST, 0x00)
+    }
+
+    //bytes waiting in the FIFO
+    pub fn fifo_count(&mut self) -> Result<u16, ImcError<E>> {
+        let mut buffer = [0; 2];
+        self.read_regs(FIFO_COUNTH, &mut buffer)?;
+        Ok(u16::from_be_bytes([buffer[0] & 0x1F, buffer[1]]))
+    }
+
+    //FIFO_OVERFLOW_INT, clears on read
+    pub fn fifo_overflow(&mut self) -> Result<bool, ImcError<E>> {
+        Ok(self.read_reg(INT_STATUS_2)? & 0x1F != 0)
+    }
+
+    //drain as many whole frames as fit into `frames`
+    pub fn fifo_read(&mut self, frames: &mut [FifoFrame]) -> Result<FifoRead, ImcError<E>> {
+        let overflow = self.fifo_overflow()?;
+        //Stream mode overwrote the oldest data and the frame boundaries are lost, start again.
+        //a full snapshot is what was asked for
+        if overflow && self.fifo.mode == FifoMode::Stream {
+            self.fifo_reset()?;
+            return Ok(FifoRead {
+                frames: 0,
+                overflow,
+            });
+        }
+
+        let frame_len = self.fifo.frame_len();
+        if frame_len == 0 {
+            return Ok(FifoRead {
+                frames: 0,
+                overflow,
+            });
+        }
+
+        let available = usize::from(self.fifo_count()?) / frame_len;
+        let count = available.min(frames.len());
+
+        let mut buffer = [0; READ_CHUNK];
+        let per_chunk = READ_CHUNK / frame_len;
+        for chunk in frames[..count].chunks_mut(per_chunk) {
+            let bytes = &mut buffer[..chunk.len() * frame_len];
+            self.read_regs(FIFO_R_W, bytes)?;
+
+            for (frame, data) in chunk.iter_mut().zip(bytes.chunks_exact(frame_len)) {
+                *frame = self.decode_fifo_frame(data);
+            }
+        }
+
+        Ok(FifoRead {
+            frames: count,
+            overflow,
+        })
+    }
+
+    fn decode_fifo_frame(&mut self, mut data: &[u8]) -> FifoFrame {
+        let mut frame = FifoFrame {
+            timestamp_us: self.fifo_timestamp_us(self.fifo_frames),
+            ..FifoFrame::default()
+        };
+        self.fifo_frames += 1;
+
+        if self.fifo.accel {
+            frame.acc = Some(decode_accel(
+                &data[..ACCEL_LEN],
+                self.accel_range,
+                &self.mounting,
+            ));
+            data = &data[ACCEL_LEN..];
+        }
+        if self.fifo.gyro {
+            frame.gyro = Some(decode_gyro(
+                &data[..GYRO_LEN],
+                self.gyro_range,
+                &self.mounting,
+            ));
+            data = &data[GYRO_LEN..];
+        }
+        if self.fifo.temperature {
+            frame.temperature = Some(decode_temperature(&data[..TEMP_LEN]));
+            data = &data[TEMP_LEN..];
+        }
+        if self.fifo.mag {
+            let mut mag = [0; MAG_LEN];
+            mag.copy_from_slice(&data[..MAG_LEN]);
+            frame.mag = Some(decode_mag(&mag, &self.mounting));
+        }
+        frame
+    }
+
+    //frames are written at the gyro ODR when it's in the FIFO, the accel ODR otherwise
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    fn fifo_timestamp_us(&self, frame: u64) -> u64 {
+        let odr = if self.fifo.gyro {
+            self.gyro_odr()
+        } else {
+            self.accel_odr()
+        };
+        (frame as f64 * 1_000_000.0 / f64::from(odr)) as u64
+    }
+}
@@ -0,0 +1,16 @@
+//driver logging, through defmt on the target, log on a host, or compiled out with neither.
+//features are additive, so with both on defmt wins
+
+//format strings have to suit both defmt and core::fmt
+macro_rules! info {
+    ($s:literal $(, $x:expr)* $(,)?) => {{
+        #[cfg(feature = "defmt")]
+        ::defmt::info!($s $(, $x)*);
+        #[cfg(all(feature = "log", not(feature = "defmt")))]
+        ::log::info!($s $(, $x)*);
+        #[cfg(not(any(feature = "defmt", feature = "log")))]
+        let _ = ($(&$x),*);
+    }};
+}
+
+pub(crate) use info;
@@ -0,0 +1,144 @@
+//AK09916 access through the ICM-20948 auxiliary I2C master
+//
+//SLV0 continuously reads ST1..ST2 into EXT_SLV_SENS_DATA so the mag comes back with the
+//accel and gyro, SLV4 does one off register reads and writes for configuration. SLV0 is
+//paused while SLV4 polls for a single measurement, its ST2 read would release the data first.
+//the register values are shared with AsyncImc20948 and the DMP's compass setup
+
+use crate::{
+    register::{
+        ak09916, I2cMstCtrl, Register, I2C_SLV0_ADDR, I2C_SLV0_CTRL, I2C_SLV0_REG, I2C_SLV4_ADDR,
+        I2C_SLV4_DO, I2C_SLV4_REG,
+    },
+    ImcError, MAG_ADDR,
+};
+#[cfg(feature = "blocking")]
+use crate::{
+    register::{IntPinCfg, UserCtrl, I2C_MST_STATUS, I2C_SLV4_CTRL, I2C_SLV4_DI},
+    Imc20948, MagAccess, Transport,
+};
+
+//I2C_SLVx_ADDR read flag
+pub const SLV_RNW: u8 = 0x80;
+//I2C_SLVx_CTRL enable flag
+pub const SLV_EN: u8 = 0x80;
+//I2C_MST_STATUS flags
+const SLV4_DONE: u8 = 0x40;
+const SLV4_NACK: u8 = 0x10;
+//345.6kHz, the closest to 400kHz
+const MST_CLK: u8 = 7;
+//ST1..ST2
+const MAG_BLOCK_LEN: u8 = 9;
+//status polls before giving up on a SLV4 transaction
+pub const SLV4_POLLS: u32 = 1000;
+
+//stop between reads, the AK09916 doesn't support repeated start
+pub const MST_CTRL: I2cMstCtrl = I2cMstCtrl(0)
+    .with_i2c_mst_p_nsr(true)
+    .with_i2c_mst_clk(MST_CLK);
+
+//SLV0 copying ST1..ST2 into EXT_SLV_SENS_DATA_00..08 every sample
+pub const MAG_AUTO_READ: [(Register, u8); 3] = [
+    (I2C_SLV0_ADDR, SLV_RNW | MAG_ADDR),
+    (I2C_SLV0_REG, ak09916::ST1),
+    (I2C_SLV0_CTRL, SLV_EN | MAG_BLOCK_LEN),
+];
+
+//SLV4 set up to read reg, the byte lands in I2C_SLV4_DI
+pub const fn slv4_read_setup(reg: u8) -> [(Register, u8); 2] {
+    [(I2C_SLV4_ADDR, SLV_RNW | MAG_ADDR), (I2C_SLV4_REG, reg)]
+}
+
+pub const fn slv4_write_setup(reg: u8, value: u8) -> [(Register, u8); 3] {
+    [
+        (I2C_SLV4_ADDR, MAG_ADDR),
+        (I2C_SLV4_REG, reg),
+        (I2C_SLV4_DO, value),
+    ]
+}
+
+//I2C_MST_STATUS after starting SLV4, true once the transaction is done
+pub const fn slv4_done<E>(status: u8) -> Result<bool, ImcError<E>> {
+    if status & SLV4_NACK != 0 {
+        return Err(ImcError::AuxNack);
+    }
+    Ok(status & SLV4_DONE != 0)
+}
+
+//I2C_SLV0_CTRL with SLV0 stopped, None if it wasn't running
+pub const fn slv0_paused(ctrl: u8) -> Option<u8> {
+    if ctrl & SLV_EN == 0 {
+        None
+    } else {
+        Some(ctrl & !SLV_EN)
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl<T, E> Imc20948<T, E>
+where
+    T: Transport<Error = E>,
+{
+    //drive the mag from the ICM-20948's own I2C master rather than the host bus
+    pub fn imu_enable_i2c_master(&mut self) -> Result<(), ImcError<E>> {
+        //the aux bus can't be bridged and mastered at the same time
+        self.modify(|r: IntPinCfg| r.with_bypass_en(false))?;
+        self.write(MST_CTRL)?;
+        self.modify(|r: UserCtrl| r.with_i2c_mst_en(true))?;
+
+        self.mag_access = MagAccess::AuxMaster;
+        Ok(())
+    }
+
+    pub(crate) fn mag_start_auto_read(&mut self) -> Result<(), ImcError<E>> {
+        for (reg, value) in MAG_AUTO_READ {
+            self.write_config(reg, value)?;
+        }
+        Ok(())
+    }
+
+    //stop SLV0, returning the I2C_SLV0_CTRL value to resume it with, None if it wasn't running
+    pub(crate) fn mag_pause_auto_read(&mut self) -> Result<Option<u8>, ImcError<E>> {
+        if self.mag_access != MagAccess::AuxMaster {
+            return Ok(None);
+        }
+        let ctrl = self.read_reg(I2C_SLV0_CTRL)?;
+        let Some(paused) = slv0_paused(ctrl) else {
+            return Ok(None);
+        };
+        self.write_config(I2C_SLV0_CTRL, paused)?;
+        Ok(Some(ctrl))
+    }
+
+    pub(crate) fn mag_resume_auto_read(&mut self, ctrl: Option<u8>) -> Result<(), ImcError<E>> {
+        ctrl.map_or(Ok(()), |ctrl| self.write_config(I2C_SLV0_CTRL, ctrl))
+    }
+
+    pub(crate) fn slv4_read(&mut self, reg: u8) -> Result<u8, ImcError<E>> {
+        for (reg, value) in slv4_read_setup(reg) {
+            self.write_config(reg, value)?;
+        }
+        self.slv4_transfer()?;
+        self.read_reg(I2C_SLV4_DI)
+    }
+
+    pub(crate) fn slv4_write(&mut self, reg: u8, value: u8) -> Result<(), ImcError<E>> {
+        for (reg, value) in slv4_write_setup(reg, value) {
+            self.write_config(reg, value)?;
+        }
+        self.slv4_transfer()
+    }
+
+    fn slv4_transfer(&mut self) -> Result<(), ImcError<E>> {
+        //SLV4_EN self clears when the transaction is done
+        self.write_reg(I2C_SLV4_CTRL, SLV_EN)?;
+
+        for _ in 0..SLV4_POLLS {
+            //status clears on read
+            if slv4_done(self.read_reg(I2C_MST_STATUS)?)? {
+                return Ok(());
+            }
+        }
+        Err(ImcError::Timeout)
+    }
+}
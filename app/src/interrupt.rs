@@ -0,0 +1,84 @@
+//ICM-20948 INT pin configuration and interrupt sources
+
+use crate::{
+    register::{IntEnable, IntPinCfg, INT_ENABLE_1, INT_ENABLE_2, INT_ENABLE_3, INT_STATUS},
+    Imc20948, ImcError, Transport,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntLevel {
+    #[default]
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntLatch {
+    //50us pulse per event
+    #[default]
+    Pulse,
+    //held until the interrupt status is read
+    Latched,
+}
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InterruptConfig {
+    pub level: IntLevel,
+    //open drain rather than push-pull
+    pub open_drain: bool,
+    pub latch: IntLatch,
+    //a latched interrupt clears on any register read, not just INT_STATUS
+    pub clear_on_any_read: bool,
+    pub raw_data_ready: bool,
+    pub fifo_watermark: bool,
+    pub fifo_overflow: bool,
+    pub wake_on_motion: bool,
+}
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InterruptStatus {
+    pub raw_data_ready: bool,
+    pub fifo_watermark: bool,
+    pub fifo_overflow: bool,
+    pub wake_on_motion: bool,
+}
+
+impl<T, E> Imc20948<T, E>
+where
+    T: Transport<Error = E>,
+{
+    pub fn configure_interrupts(&mut self, config: &InterruptConfig) -> Result<(), ImcError<E>> {
+        //keep BYPASS_EN, it shares the register
+        self.modify(|r: IntPinCfg| {
+            r.with_int1_actl(config.level == IntLevel::ActiveLow)
+                .with_int1_open(config.open_drain)
+                .with_int1_latch_en(config.latch == IntLatch::Latched)
+                .with_int_anyrd_2clear(config.clear_on_any_read)
+        })?;
+
+        self.modify(|r: IntEnable| r.with_wom_int_en(config.wake_on_motion))?;
+        //RAW_DATA_0_RDY_EN
+        self.write_config(INT_ENABLE_1, u8::from(config.raw_data_ready))?;
+        //FIFO_OVERFLOW_EN and FIFO_WM_EN, one bit per FIFO
+        self.write_config(INT_ENABLE_2, if config.fifo_overflow { 0x1F } else { 0x00 })?;
+        self.write_config(
+            INT_ENABLE_3,
+            if config.fifo_watermark { 0x1F } else { 0x00 },
+        )
+    }
+
+    //read and clear INT_STATUS..INT_STATUS_3
+    pub fn interrupt_status(&mut self) -> Result<InterruptStatus, ImcError<E>> {
+        let mut buffer = [0; 4];
+        self.read_regs(INT_STATUS, &mut buffer)?;
+
+        Ok(InterruptStatus {
+            wake_on_motion: buffer[0] & 0x08 != 0,
+            raw_data_ready: buffer[1] & 0x01 != 0,
+            fifo_overflow: buffer[2] & 0x1F != 0,
+            fifo_watermark: buffer[3] & 0x1F != 0,
+        })
+    }
+}
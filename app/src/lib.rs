@@ -2,149 +2,875 @@
 #![warn(clippy::pedantic, clippy::nursery)]
 #![allow(clippy::missing_errors_doc)]
 
-use core::{f32::consts::PI, fmt::Debug};
-use defmt::info;
-use embedded_hal::blocking::i2c;
-use nalgebra::Vector3;
-
-const MAG_ADDR: i2c::SevenBitAddress = 0x0c;
-const IMU_ADDR: i2c::SevenBitAddress = 0x68;
-
-pub struct Imc20948<I, E>
+#[cfg(not(any(feature = "blocking", feature = "async")))]
+compile_error!("enable the blocking or async feature, or both");
+
+use core::f32::consts::PI;
+#[cfg(feature = "blocking")]
+use embedded_hal::{
+    blocking::{delay::DelayMs, i2c, spi},
+    digital::v2::OutputPin,
+};
+#[cfg(any(feature = "embedded-hal-1", feature = "async"))]
+use embedded_hal_1::i2c::ErrorKind;
+#[cfg(feature = "blocking")]
+use fmt::info;
+use nalgebra::{Rotation3, Vector3};
+#[cfg(feature = "blocking")]
+use register::{
+    ak09916, Bank, IntPinCfg, PwrMgmt1, PwrMgmt2, RegisterValue, UserCtrl, ACCEL_SMPLRT_DIV_1,
+    ACCEL_SMPLRT_DIV_2, ACCEL_XOUT_H, EXT_SLV_SENS_DATA_00, GYRO_SMPLRT_DIV, PWR_MGMT_1,
+    REG_BANK_SEL, USER_CTRL, WHO_AM_I,
+};
+use register::{AccelConfig, GyroConfig1, Register};
+
+pub use altitude::{pressure_to_altitude, sea_level_pressure, Altimeter, STANDARD_SEA_LEVEL_PA};
+#[cfg(feature = "async")]
+pub use asynch::AsyncImc20948;
+#[cfg(feature = "async")]
+pub use bmp280::AsyncBmp280;
+#[cfg(feature = "blocking")]
+pub use bmp280::Bmp280;
+pub use bmp280::{
+    BmpAddress, BmpCalibration, BmpConfig, BmpError, BmpMode, BmpSample, IirFilter, Oversampling,
+    Standby,
+};
+#[cfg(feature = "blocking")]
+pub use bus::{MutexDevice, RefCellDevice};
+#[cfg(feature = "blocking")]
+pub use dmp::{DmpConfig, DmpPacket};
+#[cfg(feature = "blocking")]
+pub use fifo::{FifoConfig, FifoFrame, FifoMode, FifoRead};
+#[cfg(feature = "blocking")]
+pub use interrupt::{IntLatch, IntLevel, InterruptConfig, InterruptStatus};
+#[cfg(feature = "blocking")]
+pub use low_power::{AccelAveraging, GyroAveraging, LowPowerConfig};
+pub use mag::{MagAccess, MagMode, MagSample, MagSelfTest};
+#[cfg(feature = "blocking")]
+pub use self_test::{AxisSelfTest, SelfTestReport};
+#[cfg(feature = "embedded-hal-1")]
+pub use transport::Eh1I2cTransport;
+#[cfg(feature = "blocking")]
+pub use transport::{I2cTransport, SpiError, SpiTransport, Transport};
+
+mod altitude;
+#[cfg(feature = "async")]
+mod asynch;
+mod bmp280;
+#[cfg(feature = "blocking")]
+mod bus;
+#[cfg(feature = "blocking")]
+mod dmp;
+#[cfg(feature = "blocking")]
+mod fifo;
+mod fmt;
+mod i2c_master;
+#[cfg(feature = "blocking")]
+mod interrupt;
+#[cfg(feature = "blocking")]
+mod low_power;
+pub mod mag;
+#[cfg(feature = "blocking")]
+mod offset;
+pub mod register;
+#[cfg(feature = "blocking")]
+mod self_test;
+#[cfg(feature = "blocking")]
+mod transport;
+
+const MAG_ADDR: u8 = 0x0c;
+
+const IMU_ID: u8 = 0xEA;
+const MAG_ID: u16 = 0x0948;
+
+//register access after a device reset
+const RESET_DELAY_MS: u32 = 100;
+//gyro start-up from sleep, accel is quicker
+const WAKE_DELAY_MS: u32 = 35;
+//CLKSEL value that picks the PLL when it's ready, falling back to the internal oscillator
+const CLKSEL_AUTO: u8 = 1;
+//AK09916 needs 100us in power down between modes, and after a soft reset
+const MAG_MODE_DELAY_MS: u32 = 1;
+//give up waiting for a single or self-test measurement after this many ms
+const MAG_MEASURE_TIMEOUT_MS: u32 = 100;
+//ACCEL_SMPLRT_DIV is 12 bits
+const MAX_ACCEL_DIVIDER: u16 = 0x0FFF;
+
+//TEMP_OUT LSB per degree C and the reading at 21C
+const TEMP_SENSITIVITY: f32 = 333.87;
+const TEMP_OFFSET: f32 = 0.0;
+const TEMP_ROOM: f32 = 21.0;
+
+#[cfg(feature = "blocking")]
+pub struct Imc20948<T, E>
 where
-    I: i2c::Read<Error = E> + i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
+    T: Transport<Error = E>,
 {
-    i2c: I,
+    transport: T,
+    //None until the first bank select, or after a reset
+    bank: Option<Bank>,
+    gyro_range: GyroRange,
+    accel_range: AccelRange,
+    gyro_bandwidth: GyroBandwidth,
+    accel_bandwidth: AccelBandwidth,
+    gyro_divider: u8,
+    accel_divider: u16,
+    mag_mode: MagMode,
+    mag_access: MagAccess,
+    //board to vehicle frame
+    mounting: Rotation3<f32>,
+    fifo: FifoConfig,
+    //frames read since the FIFO was last reset, for timestamps
+    fifo_frames: u64,
+    verify: bool,
+}
+
+//set by the AD0 pin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImuAddress {
+    //0x68
+    #[default]
+    Ad0Low,
+    //0x69
+    Ad0High,
+}
+
+impl ImuAddress {
+    #[must_use]
+    pub const fn addr(self) -> u8 {
+        match self {
+            Self::Ad0Low => 0x68,
+            Self::Ad0High => 0x69,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GyroRange {
+    #[default]
+    Dps250,
+    Dps500,
+    Dps1000,
+    Dps2000,
+}
+
+impl GyroRange {
+    //GYRO_FS_SEL value
+    const fn bits(self) -> u8 {
+        self as u8
+    }
+
+    //LSB per degree per second
+    #[must_use]
+    pub const fn sensitivity(self) -> f32 {
+        match self {
+            Self::Dps250 => 131.0,
+            Self::Dps500 => 65.5,
+            Self::Dps1000 => 32.8,
+            Self::Dps2000 => 16.4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccelRange {
+    #[default]
+    G2,
+    G4,
+    G8,
+    G16,
+}
+
+impl AccelRange {
+    //ACCEL_FS_SEL value
+    const fn bits(self) -> u8 {
+        self as u8
+    }
+
+    //LSB per g
+    #[must_use]
+    pub const fn sensitivity(self) -> f32 {
+        match self {
+            Self::G2 => 16384.0,
+            Self::G4 => 8192.0,
+            Self::G8 => 4096.0,
+            Self::G16 => 2048.0,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum ImcError<E> {
     I2c(E),
+    //address or data not acknowledged, only split out on embedded-hal 1.0 buses
+    Nack(E),
+    //another master took the bus, only split out on embedded-hal 1.0 buses
+    ArbitrationLoss(E),
+    Spi(E),
     BadId,
+    BadMagId,
+    //a measurement or aux bus transaction didn't finish in time
+    Timeout,
+    //the mag didn't acknowledge on the aux bus
+    AuxNack,
+    //the operation needs the mag on the other MagAccess
+    WrongMagAccess,
+    //accel sample rate divider above MAX_ACCEL_DIVIDER
+    AccelDividerRange,
+    //the DMP image doesn't fit in DMP memory
+    DmpImageSize,
+    //DMP memory read back after loading did not match the image
+    DmpVerify {
+        address: u16,
+    },
+    //register read back after a verified write did not match
+    Verify {
+        register: Register,
+        expected: u8,
+        actual: u8,
+    },
+}
+
+#[cfg(any(feature = "embedded-hal-1", feature = "async"))]
+impl<E: embedded_hal_1::i2c::Error> ImcError<E> {
+    //classify an embedded-hal 1.0 bus error by its ErrorKind
+    pub fn from_i2c(error: E) -> Self {
+        match error.kind() {
+            ErrorKind::NoAcknowledge(_) => Self::Nack(error),
+            ErrorKind::ArbitrationLoss => Self::ArbitrationLoss(error),
+            _ => Self::I2c(error),
+        }
+    }
+}
+
+//3dB bandwidth of the gyro digital low pass filter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GyroBandwidth {
+    #[default]
+    Hz197,
+    Hz152,
+    Hz120,
+    Hz51,
+    Hz24,
+    Hz12,
+    Hz6,
+    Hz361,
+    //filter bypassed, sample rate divider ignored
+    Bypass,
+}
+
+impl GyroBandwidth {
+    //GYRO_FCHOICE and GYRO_DLPFCFG for this bandwidth, the filter is bypassed without FCHOICE
+    const fn apply(self, r: GyroConfig1) -> GyroConfig1 {
+        match self {
+            Self::Bypass => r.with_fchoice(false).with_dlpfcfg(0),
+            dlpf => r.with_fchoice(true).with_dlpfcfg(dlpf as u8),
+        }
+    }
+
+    //output data rate in Hz with this filter and GYRO_SMPLRT_DIV
+    fn odr(self, divider: u8) -> f32 {
+        match self {
+            Self::Bypass => 9000.0,
+            _ => 1100.0 / (1.0 + f32::from(divider)),
+        }
+    }
+}
+
+//3dB bandwidth of the accel digital low pass filter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccelBandwidth {
+    #[default]
+    Hz246,
+    Hz111,
+    Hz50,
+    Hz24,
+    Hz12,
+    Hz6,
+    Hz473,
+    //filter bypassed, sample rate divider ignored
+    Bypass,
+}
+
+impl AccelBandwidth {
+    //ACCEL_FCHOICE and ACCEL_DLPFCFG for this bandwidth, the filter is bypassed without FCHOICE
+    const fn apply(self, r: AccelConfig) -> AccelConfig {
+        match self {
+            Self::Bypass => r.with_fchoice(false).with_dlpfcfg(0),
+            //ACCEL_DLPFCFG 0 and 1 are both 246Hz
+            dlpf => r.with_fchoice(true).with_dlpfcfg(dlpf as u8 + 1),
+        }
+    }
+
+    //output data rate in Hz with this filter and ACCEL_SMPLRT_DIV
+    fn odr(self, divider: u16) -> f32 {
+        match self {
+            Self::Bypass => 4500.0,
+            _ => 1125.0 / (1.0 + f32::from(divider)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImuSample {
+    //radians per second
+    pub gyro: Vector3<f32>,
+    //g
+    pub acc: Vector3<f32>,
+    //die temperature in degrees C
+    pub temperature: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Config {
+    pub gyro_range: GyroRange,
+    pub accel_range: AccelRange,
+    pub gyro_bandwidth: GyroBandwidth,
+    pub accel_bandwidth: AccelBandwidth,
+    pub gyro_divider: u8,
+    //12 bits, startup fails with AccelDividerRange above 0x0FFF
+    pub accel_divider: u16,
+    pub mag_mode: MagMode,
+    pub mag_access: MagAccess,
+    //board to vehicle frame, applied to every sensor
+    pub mounting: Rotation3<f32>,
+    pub verify: bool,
 }
 
-impl<I, E> Imc20948<I, E>
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            gyro_range: GyroRange::default(),
+            accel_range: AccelRange::default(),
+            gyro_bandwidth: GyroBandwidth::default(),
+            accel_bandwidth: AccelBandwidth::default(),
+            gyro_divider: 0,
+            accel_divider: 0,
+            mag_mode: MagMode::default(),
+            mag_access: MagAccess::default(),
+            mounting: Rotation3::identity(),
+            verify: false,
+        }
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl<I, E> Imc20948<I2cTransport<I>, E>
 where
     I: i2c::Read<Error = E> + i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
 {
-    pub const fn new(i2c: I) -> Self {
-        Self { i2c }
+    pub fn new(i2c: I, address: ImuAddress) -> Self {
+        Self::with_transport(I2cTransport::new(i2c, address.addr()))
     }
+}
 
-    pub fn startup(&mut self) -> Result<(), ImcError<E>> {
+#[cfg(feature = "embedded-hal-1")]
+impl<I: embedded_hal_1::i2c::I2c> Imc20948<Eh1I2cTransport<I>, I::Error> {
+    pub fn new_eh1(i2c: I, address: ImuAddress) -> Self {
+        Self::with_transport(Eh1I2cTransport::new(i2c, address.addr()))
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl<S, P, SE, PE> Imc20948<SpiTransport<S, P>, SpiError<SE, PE>>
+where
+    S: spi::Transfer<u8, Error = SE> + spi::Write<u8, Error = SE>,
+    P: OutputPin<Error = PE>,
+{
+    //cs is the active low chip select
+    pub fn new_spi(spi: S, cs: P) -> Self {
+        Self::with_transport(SpiTransport::new(spi, cs))
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl<T, E> Imc20948<T, E>
+where
+    T: Transport<Error = E>,
+{
+    pub fn with_transport(transport: T) -> Self {
+        Self {
+            transport,
+            bank: None,
+            gyro_range: GyroRange::Dps250,
+            accel_range: AccelRange::G2,
+            gyro_bandwidth: GyroBandwidth::Hz197,
+            accel_bandwidth: AccelBandwidth::Hz246,
+            gyro_divider: 0,
+            accel_divider: 0,
+            mag_mode: MagMode::PowerDown,
+            mag_access: MagAccess::Bypass,
+            mounting: Rotation3::identity(),
+            fifo: FifoConfig::default(),
+            fifo_frames: 0,
+            verify: false,
+        }
+    }
+
+    //read back configuration registers after writing them
+    pub const fn set_verify(&mut self, verify: bool) {
+        self.verify = verify;
+    }
+
+    pub fn startup<D: DelayMs<u32>>(
+        &mut self,
+        config: &Config,
+        delay: &mut D,
+    ) -> Result<(), ImcError<E>> {
         //check id
-        let imu_id = self.imu_who_am_i().map_err(|e| ImcError::I2c(e))?;
-        if imu_id != 0xEA {
+        let imu_id = self.imu_who_am_i()?;
+        if imu_id != IMU_ID {
             return Err(ImcError::BadId);
         }
 
-        //set bank0
-        self.imu_set_bank(0).map_err(|e| ImcError::I2c(e))?;
-
         //soft reset
-        self.imu_soft_reset().map_err(|e| ImcError::I2c(e))?;
+        self.imu_soft_reset()?;
+        delay.delay_ms(RESET_DELAY_MS);
+
+        self.verify = config.verify;
+        self.mounting = config.mounting;
 
-        //wake
-        self.imu_wake().map_err(|e| ImcError::I2c(e))?;
+        if !T::I2C_INTERFACE {
+            self.modify(|r: UserCtrl| r.with_i2c_if_dis(true))?;
+        }
 
-        //full power
+        //wake, clocked from the PLL
+        self.imu_wake()?;
+        delay.delay_ms(WAKE_DELAY_MS);
+
+        //full power, all accel and gyro axes on
+        self.write(PwrMgmt2::default())?;
 
         //mag startup
+        match config.mag_access {
+            MagAccess::Bypass => self.imu_enable_i2c_bypass()?,
+            MagAccess::AuxMaster => self.imu_enable_i2c_master()?,
+        }
+        if self.mag_who_am_i()? != MAG_ID {
+            return Err(ImcError::BadMagId);
+        }
+        self.mag_soft_reset(delay)?;
+        self.set_mag_mode(config.mag_mode, delay)?;
+        if config.mag_access == MagAccess::AuxMaster {
+            self.mag_start_auto_read()?;
+        }
 
         //non minimal stuff
         //sample mode
+        self.set_gyro_bandwidth(config.gyro_bandwidth)?;
+        self.set_accel_bandwidth(config.accel_bandwidth)?;
+        self.set_gyro_sample_rate_divider(config.gyro_divider)?;
+        self.set_accel_sample_rate_divider(config.accel_divider)?;
 
         //set scales
+        self.set_gyro_range(config.gyro_range)?;
+        self.set_accel_range(config.accel_range)?;
 
         Ok(())
     }
 
-    pub fn imu_who_am_i(&mut self) -> Result<u8, E> {
-        let mut buffer = [1];
-        //who am i?
-        self.i2c.write_read(IMU_ADDR, &[0u8], &mut buffer)?;
+    pub fn imu_who_am_i(&mut self) -> Result<u8, ImcError<E>> {
         //expect EA
-        info!("ID: {:X}", buffer);
-        Ok(buffer[0])
+        let id = self.read_reg(WHO_AM_I)?;
+        info!("ID: {:X}", id);
+        Ok(id)
     }
 
-    pub fn imu_enable_i2c_bypass(&mut self) -> Result<(), E> {
-        //reset i2c master
-        self.i2c.write(IMU_ADDR, &[0x3, 0x02])?;
+    pub fn imu_enable_i2c_bypass(&mut self) -> Result<(), ImcError<E>> {
+        //reset i2c master, I2C_MST_RST self clears so can't be verified
+        let user_ctrl = self.read::<UserCtrl>()?;
+        self.write_reg(
+            USER_CTRL,
+            user_ctrl
+                .with_i2c_mst_en(false)
+                .with_i2c_mst_rst(true)
+                .bits(),
+        )?;
 
         //Enable BYPASS_EN
-        self.i2c.write(IMU_ADDR, &[0xF, 0x02])
+        self.modify(|r: IntPinCfg| r.with_bypass_en(true))?;
+
+        self.mag_access = MagAccess::Bypass;
+        Ok(())
     }
 
-    pub fn imu_wake(&mut self) -> Result<(), E> {
+    pub fn imu_wake(&mut self) -> Result<(), ImcError<E>> {
         //wake from sleep
-        self.i2c.write(IMU_ADDR, &[0x6, 0x1])
+        self.write(PwrMgmt1::default().with_clksel(CLKSEL_AUTO))
     }
 
-    pub fn imu_read(&mut self) -> Result<(Vector3<f32>, Vector3<f32>), E> {
-        let mut buffer = [0; 12];
+    pub fn imu_read(&mut self) -> Result<ImuSample, ImcError<E>> {
+        //accel, gyro then temp
+        let mut buffer = [0; 14];
 
-        self.i2c.write_read(IMU_ADDR, &[0x2Du8], &mut buffer)?;
+        self.read_regs(ACCEL_XOUT_H, &mut buffer)?;
 
-        let acc_x = f32::from(i16::from_be_bytes([buffer[0], buffer[1]]));
-        let acc_y = f32::from(i16::from_be_bytes([buffer[2], buffer[3]]));
-        let acc_z = f32::from(i16::from_be_bytes([buffer[4], buffer[5]]));
+        Ok(decode_imu(
+            &buffer,
+            self.accel_range,
+            self.gyro_range,
+            &self.mounting,
+        ))
+    }
 
-        let gyr_x = f32::from(i16::from_be_bytes([buffer[6], buffer[7]]));
-        let gyr_y = f32::from(i16::from_be_bytes([buffer[8], buffer[9]]));
-        let gyr_z = f32::from(i16::from_be_bytes([buffer[10], buffer[11]]));
+    //accel, gyro and mag, in a single burst when the aux master is reading the mag
+    pub fn read_all(&mut self) -> Result<(ImuSample, MagSample), ImcError<E>> {
+        match self.mag_access {
+            MagAccess::Bypass => {
+                let imu = self.imu_read()?;
+                Ok((imu, self.mag_read()?))
+            }
+            MagAccess::AuxMaster => {
+                //accel, gyro, temp then EXT_SLV_SENS_DATA
+                let mut buffer = [0; 23];
+                self.read_regs(ACCEL_XOUT_H, &mut buffer)?;
+
+                let imu = decode_imu(
+                    &buffer[..14],
+                    self.accel_range,
+                    self.gyro_range,
+                    &self.mounting,
+                );
+                let mut mag = [0; 9];
+                mag.copy_from_slice(&buffer[14..]);
+                Ok((imu, decode_mag(&mag, &self.mounting)))
+            }
+        }
+    }
 
-        let gyro = Vector3::new(gyr_x, gyr_y, gyr_z) * (PI / 180.0) / 131.0;
+    pub const fn mounting(&self) -> &Rotation3<f32> {
+        &self.mounting
+    }
 
-        let acc = Vector3::new(acc_x, acc_y, acc_z) / 16384.0;
-        Ok((gyro, acc))
+    //board to vehicle rotation, applied to accel, gyro and mag
+    pub const fn set_mounting(&mut self, mounting: Rotation3<f32>) {
+        self.mounting = mounting;
     }
 
-    pub fn mag_read(&mut self) -> Result<Vector3<f32>, E> {
-        let mut buffer = [0; 9];
+    pub const fn gyro_range(&self) -> GyroRange {
+        self.gyro_range
+    }
+
+    pub fn set_gyro_range(&mut self, range: GyroRange) -> Result<(), ImcError<E>> {
+        self.modify(|r: GyroConfig1| r.with_fs_sel(range.bits()))?;
+        self.gyro_range = range;
+        Ok(())
+    }
+
+    pub const fn accel_range(&self) -> AccelRange {
+        self.accel_range
+    }
 
-        self.i2c.write_read(MAG_ADDR, &[0x10], &mut buffer)?;
+    pub fn set_accel_range(&mut self, range: AccelRange) -> Result<(), ImcError<E>> {
+        self.modify(|r: AccelConfig| r.with_fs_sel(range.bits()))?;
+        self.accel_range = range;
+        Ok(())
+    }
 
-        //let status1 = buffer[0];
+    pub fn set_gyro_bandwidth(&mut self, bandwidth: GyroBandwidth) -> Result<(), ImcError<E>> {
+        self.modify(|r: GyroConfig1| bandwidth.apply(r))?;
+        self.gyro_bandwidth = bandwidth;
+        Ok(())
+    }
 
-        //realign magnetometer axis with imu
-        let mag_x = f32::from(i16::from_le_bytes([buffer[1], buffer[2]]));
-        let mag_y = f32::from(i16::from_le_bytes([buffer[3], buffer[4]]));
-        let mag_z = f32::from(i16::from_le_bytes([buffer[5], buffer[6]]));
+    pub fn set_accel_bandwidth(&mut self, bandwidth: AccelBandwidth) -> Result<(), ImcError<E>> {
+        self.modify(|r: AccelConfig| bandwidth.apply(r))?;
+        self.accel_bandwidth = bandwidth;
+        Ok(())
+    }
 
-        //buffer[7] is a dummy register
+    //gyro ODR = 1.1kHz / (1 + divider)
+    pub fn set_gyro_sample_rate_divider(&mut self, divider: u8) -> Result<(), ImcError<E>> {
+        self.write_config(GYRO_SMPLRT_DIV, divider)?;
+        self.gyro_divider = divider;
+        Ok(())
+    }
 
-        //let status2 = buffer[8];
+    //accel ODR = 1.125kHz / (1 + divider), divider is 12 bits
+    pub fn set_accel_sample_rate_divider(&mut self, divider: u16) -> Result<(), ImcError<E>> {
+        let [msb, lsb] = split_accel_divider(divider)?;
 
-        Ok(Vector3::new(mag_x, mag_y, mag_z))
+        self.modify_reg(ACCEL_SMPLRT_DIV_1, 0x0F, msb)?;
+        self.write_config(ACCEL_SMPLRT_DIV_2, lsb)?;
+        self.accel_divider = divider;
+        Ok(())
     }
 
-    pub fn mag_who_am_i(&mut self) -> Result<u16, E> {
+    //effective gyro output data rate in Hz
+    pub fn gyro_odr(&self) -> f32 {
+        self.gyro_bandwidth.odr(self.gyro_divider)
+    }
+
+    //effective accel output data rate in Hz
+    pub fn accel_odr(&self) -> f32 {
+        self.accel_bandwidth.odr(self.accel_divider)
+    }
+
+    pub fn mag_read(&mut self) -> Result<MagSample, ImcError<E>> {
+        let mut buffer = [0; 9];
+
+        match self.mag_access {
+            //reading through to ST2 releases the data lock for the next sample
+            MagAccess::Bypass => self.mag_read_regs(ak09916::ST1, &mut buffer)?,
+            //SLV0 has already done the read
+            MagAccess::AuxMaster => self.read_regs(EXT_SLV_SENS_DATA_00, &mut buffer)?,
+        }
+
+        Ok(decode_mag(&buffer, &self.mounting))
+    }
+
+    pub fn mag_who_am_i(&mut self) -> Result<u16, ImcError<E>> {
         let mut buffer = [0; 2];
         //who am i?
-        self.i2c.write_read(MAG_ADDR, &[0u8], &mut buffer)?;
-        //expect EA
-        info!("ID: {:X}", buffer);
-        Ok(u16::from_le_bytes([buffer[0], buffer[1]]))
+        self.mag_read_regs(ak09916::WIA1, &mut buffer)?;
+        let id = u16::from_le_bytes([buffer[0], buffer[1]]);
+        //expect 0948
+        info!("ID: {:X}", id);
+        Ok(id)
+    }
+
+    pub const fn mag_mode(&self) -> MagMode {
+        self.mag_mode
+    }
+
+    pub fn set_mag_mode<D: DelayMs<u32>>(
+        &mut self,
+        mode: MagMode,
+        delay: &mut D,
+    ) -> Result<(), ImcError<E>> {
+        //every mode change has to go through power down
+        self.mag_write_reg(ak09916::CNTL2, MagMode::PowerDown.bits())?;
+        self.mag_mode = MagMode::PowerDown;
+        delay.delay_ms(MAG_MODE_DELAY_MS);
+
+        if mode != MagMode::PowerDown {
+            self.mag_write_reg(ak09916::CNTL2, mode.bits())?;
+            self.mag_mode = mode;
+        }
+        Ok(())
+    }
+
+    pub fn mag_soft_reset<D: DelayMs<u32>>(&mut self, delay: &mut D) -> Result<(), ImcError<E>> {
+        //CNTL3 SRST, all registers back to power down defaults
+        self.mag_write_reg(ak09916::CNTL3, 0x01)?;
+        self.mag_mode = MagMode::PowerDown;
+        delay.delay_ms(MAG_MODE_DELAY_MS);
+        Ok(())
+    }
+
+    //take one measurement in single mode then go back to the previous mode
+    pub fn mag_measure<D: DelayMs<u32>>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<MagSample, ImcError<E>> {
+        let buffer = self.mag_one_shot(MagMode::Single, delay)?;
+        Ok(decode_mag(&buffer, &self.mounting))
+    }
+
+    pub fn mag_self_test<D: DelayMs<u32>>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<MagSelfTest, ImcError<E>> {
+        let buffer = self.mag_one_shot(MagMode::SelfTest, delay)?;
+        Ok(MagSelfTest::from_bytes(&buffer))
+    }
+
+    //one measurement in a mode that drops back to power down by itself, then back to the
+    //previous mode
+    fn mag_one_shot<D: DelayMs<u32>>(
+        &mut self,
+        mode: MagMode,
+        delay: &mut D,
+    ) -> Result<[u8; 9], ImcError<E>> {
+        let previous = self.mag_mode.settled();
+
+        //SLV0 reading ST2 would clear DRDY before SLV4 gets to poll it
+        let slv0 = self.mag_pause_auto_read()?;
+        let buffer = self
+            .set_mag_mode(mode, delay)
+            .and_then(|()| self.mag_wait_ready(delay));
+        let resumed = self.mag_resume_auto_read(slv0);
+        let buffer = buffer?;
+        resumed?;
+        self.mag_mode = MagMode::PowerDown;
+
+        self.set_mag_mode(previous, delay)?;
+        Ok(buffer)
+    }
+
+    //poll DRDY, returning the ST1..ST2 block
+    fn mag_wait_ready<D: DelayMs<u32>>(&mut self, delay: &mut D) -> Result<[u8; 9], ImcError<E>> {
+        for _ in 0..MAG_MEASURE_TIMEOUT_MS {
+            let mut buffer = [0; 9];
+            self.mag_read_regs(ak09916::ST1, &mut buffer)?;
+            if buffer[0] & 0x01 != 0 {
+                return Ok(buffer);
+            }
+            delay.delay_ms(1);
+        }
+        Err(ImcError::Timeout)
+    }
+
+    fn mag_read_regs(&mut self, reg: u8, buffer: &mut [u8]) -> Result<(), ImcError<E>> {
+        match self.mag_access {
+            MagAccess::Bypass => self.transport.bypass_read_regs(reg, buffer),
+            //SLV4 only moves a byte at a time
+            MagAccess::AuxMaster => {
+                for (reg, byte) in (reg..).zip(buffer.iter_mut()) {
+                    *byte = self.slv4_read(reg)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn mag_write_reg(&mut self, reg: u8, value: u8) -> Result<(), ImcError<E>> {
+        match self.mag_access {
+            MagAccess::Bypass => self.transport.bypass_write_reg(reg, value),
+            MagAccess::AuxMaster => self.slv4_write(reg, value),
+        }
+    }
+
+    fn imu_soft_reset(&mut self) -> Result<(), ImcError<E>> {
+        //DEVICE_RESET self clears so can't be verified
+        let pwr_mgmt_1 = self.read::<PwrMgmt1>()?;
+        self.write_reg(PWR_MGMT_1, pwr_mgmt_1.with_device_reset(true).bits())?;
+
+        //reset puts the chip back in bank 0, but don't trust it until it's read back
+        self.bank = None;
+        Ok(())
     }
 
-    pub fn mag_wake(&mut self) -> Result<(), E> {
-        //enable 100hz read
-        self.i2c.write(MAG_ADDR, &[0x31, 0x8])
+    //switch bank only when the cached bank differs
+    fn select_bank(&mut self, bank: Bank) -> Result<(), ImcError<E>> {
+        if self.bank != Some(bank) {
+            self.bank = None;
+            self.transport.write_regs(REG_BANK_SEL, &[bank.bits()])?;
+            self.bank = Some(bank);
+        }
+        Ok(())
     }
 
-    fn imu_soft_reset(&mut self) -> Result<(), E> {
+    fn read_reg(&mut self, reg: Register) -> Result<u8, ImcError<E>> {
         let mut buffer = [0; 1];
-        self.i2c.write_read(IMU_ADDR, &[0x06], &mut buffer)?;
+        self.read_regs(reg, &mut buffer)?;
+        Ok(buffer[0])
+    }
 
-        buffer[0] |= 0x01;
+    //burst read starting at reg, all registers must be in the same bank
+    fn read_regs(&mut self, reg: Register, buffer: &mut [u8]) -> Result<(), ImcError<E>> {
+        self.select_bank(reg.bank)?;
+        self.transport.read_regs(reg.addr, buffer)
+    }
 
-        self.i2c.write(IMU_ADDR, &[0x06, 0x01])
+    //unverified write, for self clearing bits and data ports
+    fn write_reg(&mut self, reg: Register, value: u8) -> Result<(), ImcError<E>> {
+        self.select_bank(reg.bank)?;
+        self.transport.write_regs(reg.addr, &[value])
     }
-    fn imu_set_bank(&mut self, bank: u8) -> Result<(), E> {
-        //error if bank > 3
 
-        let bank = (bank << 4) & 0x30;
-        self.i2c.write(IMU_ADDR, &[0x7F, bank])
+    //burst write starting at reg, all registers must be in the same bank
+    fn write_regs(&mut self, reg: Register, values: &[u8]) -> Result<(), ImcError<E>> {
+        self.select_bank(reg.bank)?;
+        self.transport.write_regs(reg.addr, values)
     }
+
+    //configuration write, read back when verify is enabled
+    fn write_config(&mut self, reg: Register, value: u8) -> Result<(), ImcError<E>> {
+        self.write_reg(reg, value)?;
+
+        if self.verify {
+            check_write(reg, value, self.read_reg(reg)?)?;
+        }
+        Ok(())
+    }
+
+    //read-modify-write of the bits in mask
+    fn modify_reg(&mut self, reg: Register, mask: u8, value: u8) -> Result<(), ImcError<E>> {
+        let current = self.read_reg(reg)?;
+        self.write_config(reg, (current & !mask) | (value & mask))
+    }
+
+    fn read<R: RegisterValue>(&mut self) -> Result<R, ImcError<E>> {
+        Ok(R::from_bits(self.read_reg(R::REGISTER)?))
+    }
+
+    fn write<R: RegisterValue>(&mut self, value: R) -> Result<(), ImcError<E>> {
+        self.write_config(R::REGISTER, value.bits())
+    }
+
+    fn modify<R: RegisterValue>(&mut self, f: impl FnOnce(R) -> R) -> Result<(), ImcError<E>> {
+        let current = self.read::<R>()?;
+        self.write(f(current))
+    }
+}
+
+//ACCEL_XOUT_H..TEMP_OUT_L
+fn decode_imu(
+    buffer: &[u8],
+    accel_range: AccelRange,
+    gyro_range: GyroRange,
+    mounting: &Rotation3<f32>,
+) -> ImuSample {
+    ImuSample {
+        gyro: decode_gyro(&buffer[6..12], gyro_range, mounting),
+        acc: decode_accel(&buffer[..6], accel_range, mounting),
+        temperature: decode_temperature(&buffer[12..14]),
+    }
+}
+
+//ACCEL_XOUT_H..ACCEL_ZOUT_L
+fn decode_accel(buffer: &[u8], range: AccelRange, mounting: &Rotation3<f32>) -> Vector3<f32> {
+    let acc_x = f32::from(i16::from_be_bytes([buffer[0], buffer[1]]));
+    let acc_y = f32::from(i16::from_be_bytes([buffer[2], buffer[3]]));
+    let acc_z = f32::from(i16::from_be_bytes([buffer[4], buffer[5]]));
+
+    let acc = Vector3::new(acc_x, acc_y, acc_z) / range.sensitivity();
+    mounting * acc
+}
+
+//GYRO_XOUT_H..GYRO_ZOUT_L
+fn decode_gyro(buffer: &[u8], range: GyroRange, mounting: &Rotation3<f32>) -> Vector3<f32> {
+    let gyr_x = f32::from(i16::from_be_bytes([buffer[0], buffer[1]]));
+    let gyr_y = f32::from(i16::from_be_bytes([buffer[2], buffer[3]]));
+    let gyr_z = f32::from(i16::from_be_bytes([buffer[4], buffer[5]]));
+
+    let gyro = Vector3::new(gyr_x, gyr_y, gyr_z) * (PI / 180.0) / range.sensitivity();
+    mounting * gyro
+}
+
+//ST1..ST2, already realigned with the imu axes
+fn decode_mag(buffer: &[u8; 9], mounting: &Rotation3<f32>) -> MagSample {
+    let sample = MagSample::from_bytes(buffer);
+    MagSample {
+        field: mounting * sample.field,
+        ..sample
+    }
+}
+
+//ACCEL_SMPLRT_DIV_1 and ACCEL_SMPLRT_DIV_2
+const fn split_accel_divider<E>(divider: u16) -> Result<[u8; 2], ImcError<E>> {
+    if divider > MAX_ACCEL_DIVIDER {
+        return Err(ImcError::AccelDividerRange);
+    }
+    Ok(divider.to_be_bytes())
+}
+
+//a verified write's read back
+const fn check_write<E>(register: Register, expected: u8, actual: u8) -> Result<(), ImcError<E>> {
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(ImcError::Verify {
+            register,
+            expected,
+            actual,
+        })
+    }
+}
+
+//TEMP_OUT_H..TEMP_OUT_L
+fn decode_temperature(buffer: &[u8]) -> f32 {
+    let temp = f32::from(i16::from_be_bytes([buffer[0], buffer[1]]));
+    (temp - TEMP_OFFSET) / TEMP_SENSITIVITY + TEMP_ROOM
 }
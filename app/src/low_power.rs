@@ -0,0 +1,103 @@
+//ICM-20948 wake-on-motion, duty-cycled low power and sleep
+//
+//in duty-cycled mode the sample rate dividers set how often the sensors wake, and the
+//averaging filters set how many samples are taken each time
+
+use crate::{
+    register::{
+        AccelConfig2, AccelIntelCtrl, GyroConfig2, IntEnable, LpConfig, PwrMgmt1, PwrMgmt2,
+        ACCEL_WOM_THR,
+    },
+    Imc20948, ImcError, Transport,
+};
+
+//ACCEL_WOM_THR LSB
+const WOM_MG_PER_LSB: u16 = 4;
+//PWR_MGMT_2 DISABLE_GYRO, all axes
+const DISABLE_GYRO_ALL: u8 = 0x07;
+
+//accel samples averaged per duty cycle, DEC3_CFG
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccelAveraging {
+    //1 sample with the DLPF bypassed, 4 with it enabled
+    #[default]
+    X1Or4,
+    X8,
+    X16,
+    X32,
+}
+
+//gyro samples averaged per duty cycle, GYRO_AVGCFG
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GyroAveraging {
+    #[default]
+    X1,
+    X2,
+    X4,
+    X8,
+    X16,
+    X32,
+    X64,
+    X128,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LowPowerConfig {
+    pub accel_averaging: AccelAveraging,
+    pub gyro_averaging: GyroAveraging,
+    //power the gyro down entirely rather than duty cycling it
+    pub gyro_off: bool,
+}
+
+impl<T, E> Imc20948<T, E>
+where
+    T: Transport<Error = E>,
+{
+    //interrupt when any accel axis changes by more than threshold_mg between samples
+    pub fn enable_wake_on_motion(&mut self, threshold_mg: u16) -> Result<(), ImcError<E>> {
+        let threshold = u8::try_from(threshold_mg / WOM_MG_PER_LSB).unwrap_or(u8::MAX);
+        self.write_config(ACCEL_WOM_THR, threshold)?;
+
+        //compare each sample with the previous one
+        self.write(
+            AccelIntelCtrl::default()
+                .with_accel_intel_en(true)
+                .with_accel_intel_mode_int(true),
+        )?;
+        self.modify(|r: IntEnable| r.with_wom_int_en(true))
+    }
+
+    pub fn disable_wake_on_motion(&mut self) -> Result<(), ImcError<E>> {
+        self.modify(|r: IntEnable| r.with_wom_int_en(false))?;
+        self.write(AccelIntelCtrl::default())
+    }
+
+    pub fn enter_low_power(&mut self, config: &LowPowerConfig) -> Result<(), ImcError<E>> {
+        self.modify(|r: AccelConfig2| r.with_dec3_cfg(config.accel_averaging as u8))?;
+        self.modify(|r: GyroConfig2| r.with_avgcfg(config.gyro_averaging as u8))?;
+
+        if config.gyro_off {
+            self.write(PwrMgmt2::default().with_disable_gyro(DISABLE_GYRO_ALL))?;
+        }
+
+        self.write(
+            LpConfig::default()
+                .with_i2c_mst_cycle(true)
+                .with_accel_cycle(true)
+                .with_gyro_cycle(!config.gyro_off),
+        )?;
+        self.modify(|r: PwrMgmt1| r.with_lp_en(true))
+    }
+
+    //back to continuous sampling with every axis on
+    pub fn leave_low_power(&mut self) -> Result<(), ImcError<E>> {
+        self.modify(|r: PwrMgmt1| r.with_lp_en(false))?;
+        self.write(LpConfig::default())?;
+        self.write(PwrMgmt2::default())
+    }
+
+    //everything off except the serial interface, imu_wake to come back
+    pub fn sleep(&mut self) -> Result<(), ImcError<E>> {
+        self.modify(|r: PwrMgmt1| r.with_sleep(true))
+    }
+}
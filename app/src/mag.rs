@@ -0,0 +1,113 @@
+//AK09916 magnetometer samples
+
+use nalgebra::Vector3;
+
+//microtesla per LSB
+pub const MAG_SENSITIVITY: f32 = 0.15;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MagSample {
+    //microtesla
+    pub field: Vector3<f32>,
+    //ST1 DRDY, a new measurement since the last read
+    pub data_ready: bool,
+    //ST1 DOR, a measurement was skipped because the last one wasn't read
+    pub data_overrun: bool,
+    //ST2 HOFL, the measurement overflowed and the field is meaningless
+    pub overflow: bool,
+}
+
+impl MagSample {
+    //decode the ST1..ST2 register block into the accel/gyro frame
+    pub(crate) fn from_bytes(buffer: &[u8; 9]) -> Self {
+        let status1 = buffer[0];
+
+        let mag_x = f32::from(i16::from_le_bytes([buffer[1], buffer[2]]));
+        let mag_y = f32::from(i16::from_le_bytes([buffer[3], buffer[4]]));
+        let mag_z = f32::from(i16::from_le_bytes([buffer[5], buffer[6]]));
+
+        //buffer[7] is a dummy register
+
+        let status2 = buffer[8];
+
+        //realign magnetometer axis with imu, the AK09916 y and z point the other way
+        Self {
+            field: Vector3::new(mag_x, -mag_y, -mag_z) * MAG_SENSITIVITY,
+            data_ready: status1 & 0x01 != 0,
+            data_overrun: status1 & 0x02 != 0,
+            overflow: status2 & 0x08 != 0,
+        }
+    }
+
+    //fresh and in range
+    #[must_use]
+    pub const fn is_valid(&self) -> bool {
+        self.data_ready && !self.overflow
+    }
+}
+
+//AK09916 CNTL2 operating modes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MagMode {
+    PowerDown = 0x00,
+    //one measurement then back to power down
+    Single = 0x01,
+    Continuous10Hz = 0x02,
+    Continuous20Hz = 0x04,
+    Continuous50Hz = 0x06,
+    #[default]
+    Continuous100Hz = 0x08,
+    //one self-test measurement then back to power down
+    SelfTest = 0x10,
+}
+
+impl MagMode {
+    pub(crate) const fn bits(self) -> u8 {
+        self as u8
+    }
+
+    //the mode the chip ends up in, one shot modes power down when done
+    pub(crate) const fn settled(self) -> Self {
+        match self {
+            Self::Single | Self::SelfTest => Self::PowerDown,
+            mode => mode,
+        }
+    }
+}
+
+//raw self-test measurement, in LSB and the AK09916's own axes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MagSelfTest {
+    pub x: i16,
+    pub y: i16,
+    pub z: i16,
+}
+
+impl MagSelfTest {
+    //decode the ST1..ST2 register block, keeping raw counts
+    pub(crate) const fn from_bytes(buffer: &[u8; 9]) -> Self {
+        Self {
+            x: i16::from_le_bytes([buffer[1], buffer[2]]),
+            y: i16::from_le_bytes([buffer[3], buffer[4]]),
+            z: i16::from_le_bytes([buffer[5], buffer[6]]),
+        }
+    }
+
+    //datasheet self-test judgement limits
+    #[must_use]
+    pub const fn passed(&self) -> bool {
+        (self.x >= -200 && self.x <= 200)
+            && (self.y >= -200 && self.y <= 200)
+            && (self.z >= -1000 && self.z <= -200)
+    }
+}
+
+//how the AK09916 on the ICM-20948's aux bus is reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MagAccess {
+    //aux bus bridged onto the host bus, mag read directly
+    #[default]
+    Bypass,
+    //ICM-20948 I2C master reads the mag into EXT_SLV_SENS_DATA
+    AuxMaster,
+}
@@ -0,0 +1,102 @@
+//ICM-20948 accel and gyro offset registers
+//
+//the offsets are added to the sensor output in silicon, so they apply to the data registers,
+//the FIFO and the DMP alike. they're in the chip's own axes and independent of the full scale
+//range. the accel offsets hold a factory trim, so adjust them rather than overwrite them
+
+use crate::{
+    register::{XA_OFFS_H, XA_OFFS_L, XG_OFFS_USRH, YA_OFFS_H, YA_OFFS_L, ZA_OFFS_H, ZA_OFFS_L},
+    Imc20948, ImcError, Register, Transport,
+};
+use core::f32::consts::PI;
+use nalgebra::Vector3;
+use num_traits::float::FloatCore;
+
+//XG_OFFS_USR LSB, 4x the +-250dps output LSB
+const GYRO_OFFSET_DPS_PER_LSB: f32 = 4.0 / 131.0;
+//XA_OFFS LSB
+const ACCEL_OFFSET_G_PER_LSB: f32 = 0.000_98;
+//XA_OFFS is 15 bits, [14:7] in the high register and [6:0] in bits [7:1] of the low one
+const ACCEL_OFFSET_MIN: f32 = -16384.0;
+const ACCEL_OFFSET_MAX: f32 = 16383.0;
+//XA_OFFS_L bit 0 is reserved and has to be kept
+const ACCEL_OFFSET_L_MASK: u8 = 0xFE;
+
+const ACCEL_OFFSET_REGS: [(Register, Register); 3] = [
+    (XA_OFFS_H, XA_OFFS_L),
+    (YA_OFFS_H, YA_OFFS_L),
+    (ZA_OFFS_H, ZA_OFFS_L),
+];
+
+impl<T, E> Imc20948<T, E>
+where
+    T: Transport<Error = E>,
+{
+    //rad/s added to the gyro output, chip axes
+    pub fn gyro_offset(&mut self) -> Result<Vector3<f32>, ImcError<E>> {
+        let mut buffer = [0; 6];
+        self.read_regs(XG_OFFS_USRH, &mut buffer)?;
+
+        let offset = Vector3::from_fn(|axis, _| {
+            f32::from(i16::from_be_bytes([buffer[axis * 2], buffer[axis * 2 + 1]]))
+        });
+        Ok(offset * GYRO_OFFSET_DPS_PER_LSB * (PI / 180.0))
+    }
+
+    //rad/s, chip axes, saturates at about +-1000dps
+    pub fn set_gyro_offset(&mut self, offset: Vector3<f32>) -> Result<(), ImcError<E>> {
+        let mut buffer = [0; 6];
+        for axis in 0..3 {
+            let lsb = offset[axis] * (180.0 / PI) / GYRO_OFFSET_DPS_PER_LSB;
+            let value = to_lsb(lsb, f32::from(i16::MIN), f32::from(i16::MAX));
+            buffer[axis * 2..axis * 2 + 2].copy_from_slice(&value.to_be_bytes());
+        }
+
+        for (n, value) in (0..).zip(buffer) {
+            self.write_config(XG_OFFS_USRH.offset(n), value)?;
+        }
+        Ok(())
+    }
+
+    //g added to the accel output, chip axes
+    pub fn accel_offset(&mut self) -> Result<Vector3<f32>, ImcError<E>> {
+        let mut offset = Vector3::zeros();
+        for (axis, (high, low)) in ACCEL_OFFSET_REGS.into_iter().enumerate() {
+            let value = i16::from_be_bytes([self.read_reg(high)?, self.read_reg(low)?]) >> 1;
+            offset[axis] = f32::from(value) * ACCEL_OFFSET_G_PER_LSB;
+        }
+        Ok(offset)
+    }
+
+    //g, chip axes, saturates at about +-16g
+    pub fn set_accel_offset(&mut self, offset: Vector3<f32>) -> Result<(), ImcError<E>> {
+        for (axis, (high, low)) in ACCEL_OFFSET_REGS.into_iter().enumerate() {
+            let lsb = offset[axis] / ACCEL_OFFSET_G_PER_LSB;
+            let [value_h, value_l] =
+                (to_lsb(lsb, ACCEL_OFFSET_MIN, ACCEL_OFFSET_MAX) << 1).to_be_bytes();
+            self.write_config(high, value_h)?;
+            self.modify_reg(low, ACCEL_OFFSET_L_MASK, value_l)?;
+        }
+        Ok(())
+    }
+
+    //fold a gyro bias measured through imu_read (rad/s, mounted axes) into the offsets, so the
+    //chip subtracts it from then on
+    pub fn remove_gyro_bias(&mut self, bias: Vector3<f32>) -> Result<(), ImcError<E>> {
+        let offset = self.gyro_offset()? - self.mounting.inverse() * bias;
+        self.set_gyro_offset(offset)
+    }
+
+    //fold an accel bias measured through imu_read (g, mounted axes) into the offsets, on top of
+    //the factory trim
+    pub fn remove_accel_bias(&mut self, bias: Vector3<f32>) -> Result<(), ImcError<E>> {
+        let offset = self.accel_offset()? - self.mounting.inverse() * bias;
+        self.set_accel_offset(offset)
+    }
+}
+
+//nearest register value, clamped to its range
+#[allow(clippy::cast_possible_truncation)]
+fn to_lsb(value: f32, min: f32, max: f32) -> i16 {
+    FloatCore::round(value).clamp(min, max) as i16
+}
@@ -0,0 +1,375 @@
+//ICM-20948 register map, every register is tagged with the user bank it lives in
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bank {
+    Bank0 = 0,
+    Bank1 = 1,
+    Bank2 = 2,
+    Bank3 = 3,
+}
+
+impl Bank {
+    //USER_BANK bits of REG_BANK_SEL
+    pub(crate) const fn bits(self) -> u8 {
+        (self as u8) << 4
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Register {
+    pub bank: Bank,
+    pub addr: u8,
+}
+
+impl Register {
+    #[must_use]
+    pub const fn new(bank: Bank, addr: u8) -> Self {
+        Self { bank, addr }
+    }
+
+    //register `n` places after this one in the same bank
+    #[must_use]
+    pub const fn offset(self, n: u8) -> Self {
+        Self::new(self.bank, self.addr + n)
+    }
+}
+
+//available from every bank
+pub const REG_BANK_SEL: u8 = 0x7F;
+
+pub const WHO_AM_I: Register = Register::new(Bank::Bank0, 0x00);
+pub const USER_CTRL: Register = Register::new(Bank::Bank0, 0x03);
+pub const LP_CONFIG: Register = Register::new(Bank::Bank0, 0x05);
+pub const PWR_MGMT_1: Register = Register::new(Bank::Bank0, 0x06);
+pub const PWR_MGMT_2: Register = Register::new(Bank::Bank0, 0x07);
+pub const INT_PIN_CFG: Register = Register::new(Bank::Bank0, 0x0F);
+pub const INT_ENABLE: Register = Register::new(Bank::Bank0, 0x10);
+pub const INT_ENABLE_1: Register = Register::new(Bank::Bank0, 0x11);
+pub const INT_ENABLE_2: Register = Register::new(Bank::Bank0, 0x12);
+pub const INT_ENABLE_3: Register = Register::new(Bank::Bank0, 0x13);
+pub const I2C_MST_STATUS: Register = Register::new(Bank::Bank0, 0x17);
+pub const INT_STATUS: Register = Register::new(Bank::Bank0, 0x19);
+pub const INT_STATUS_1: Register = Register::new(Bank::Bank0, 0x1A);
+pub const INT_STATUS_2: Register = Register::new(Bank::Bank0, 0x1B);
+pub const INT_STATUS_3: Register = Register::new(Bank::Bank0, 0x1C);
+pub const SINGLE_FIFO_PRIORITY_SEL: Register = Register::new(Bank::Bank0, 0x26);
+pub const DELAY_TIMEH: Register = Register::new(Bank::Bank0, 0x28);
+pub const DELAY_TIMEL: Register = Register::new(Bank::Bank0, 0x29);
+pub const ACCEL_XOUT_H: Register = Register::new(Bank::Bank0, 0x2D);
+pub const ACCEL_XOUT_L: Register = Register::new(Bank::Bank0, 0x2E);
+pub const ACCEL_YOUT_H: Register = Register::new(Bank::Bank0, 0x2F);
+pub const ACCEL_YOUT_L: Register = Register::new(Bank::Bank0, 0x30);
+pub const ACCEL_ZOUT_H: Register = Register::new(Bank::Bank0, 0x31);
+pub const ACCEL_ZOUT_L: Register = Register::new(Bank::Bank0, 0x32);
+pub const GYRO_XOUT_H: Register = Register::new(Bank::Bank0, 0x33);
+pub const GYRO_XOUT_L: Register = Register::new(Bank::Bank0, 0x34);
+pub const GYRO_YOUT_H: Register = Register::new(Bank::Bank0, 0x35);
+pub const GYRO_YOUT_L: Register = Register::new(Bank::Bank0, 0x36);
+pub const GYRO_ZOUT_H: Register = Register::new(Bank::Bank0, 0x37);
+pub const GYRO_ZOUT_L: Register = Register::new(Bank::Bank0, 0x38);
+pub const TEMP_OUT_H: Register = Register::new(Bank::Bank0, 0x39);
+pub const TEMP_OUT_L: Register = Register::new(Bank::Bank0, 0x3A);
+//EXT_SLV_SENS_DATA_00..23 follow on consecutively
+pub const EXT_SLV_SENS_DATA_00: Register = Register::new(Bank::Bank0, 0x3B);
+pub const FIFO_EN_1: Register = Register::new(Bank::Bank0, 0x66);
+pub const FIFO_EN_2: Register = Register::new(Bank::Bank0, 0x67);
+pub const FIFO_RST: Register = Register::new(Bank::Bank0, 0x68);
+pub const FIFO_MODE: Register = Register::new(Bank::Bank0, 0x69);
+pub const FIFO_COUNTH: Register = Register::new(Bank::Bank0, 0x70);
+pub const FIFO_COUNTL: Register = Register::new(Bank::Bank0, 0x71);
+pub const FIFO_R_W: Register = Register::new(Bank::Bank0, 0x72);
+pub const DATA_RDY_STATUS: Register = Register::new(Bank::Bank0, 0x74);
+pub const HW_FIX_DISABLE: Register = Register::new(Bank::Bank0, 0x75);
+pub const FIFO_CFG: Register = Register::new(Bank::Bank0, 0x76);
+pub const MEM_START_ADDR: Register = Register::new(Bank::Bank0, 0x7C);
+pub const MEM_R_W: Register = Register::new(Bank::Bank0, 0x7D);
+pub const MEM_BANK_SEL: Register = Register::new(Bank::Bank0, 0x7E);
+
+pub const SELF_TEST_X_GYRO: Register = Register::new(Bank::Bank1, 0x02);
+pub const SELF_TEST_Y_GYRO: Register = Register::new(Bank::Bank1, 0x03);
+pub const SELF_TEST_Z_GYRO: Register = Register::new(Bank::Bank1, 0x04);
+pub const SELF_TEST_X_ACCEL: Register = Register::new(Bank::Bank1, 0x0E);
+pub const SELF_TEST_Y_ACCEL: Register = Register::new(Bank::Bank1, 0x0F);
+pub const SELF_TEST_Z_ACCEL: Register = Register::new(Bank::Bank1, 0x10);
+pub const XA_OFFS_H: Register = Register::new(Bank::Bank1, 0x14);
+pub const XA_OFFS_L: Register = Register::new(Bank::Bank1, 0x15);
+pub const YA_OFFS_H: Register = Register::new(Bank::Bank1, 0x17);
+pub const YA_OFFS_L: Register = Register::new(Bank::Bank1, 0x18);
+pub const ZA_OFFS_H: Register = Register::new(Bank::Bank1, 0x1A);
+pub const ZA_OFFS_L: Register = Register::new(Bank::Bank1, 0x1B);
+pub const TIMEBASE_CORRECTION_PLL: Register = Register::new(Bank::Bank1, 0x28);
+
+pub const GYRO_SMPLRT_DIV: Register = Register::new(Bank::Bank2, 0x00);
+pub const GYRO_CONFIG_1: Register = Register::new(Bank::Bank2, 0x01);
+pub const GYRO_CONFIG_2: Register = Register::new(Bank::Bank2, 0x02);
+pub const XG_OFFS_USRH: Register = Register::new(Bank::Bank2, 0x03);
+pub const XG_OFFS_USRL: Register = Register::new(Bank::Bank2, 0x04);
+pub const YG_OFFS_USRH: Register = Register::new(Bank::Bank2, 0x05);
+pub const YG_OFFS_USRL: Register = Register::new(Bank::Bank2, 0x06);
+pub const ZG_OFFS_USRH: Register = Register::new(Bank::Bank2, 0x07);
+pub const ZG_OFFS_USRL: Register = Register::new(Bank::Bank2, 0x08);
+pub const ODR_ALIGN_EN: Register = Register::new(Bank::Bank2, 0x09);
+pub const ACCEL_SMPLRT_DIV_1: Register = Register::new(Bank::Bank2, 0x10);
+pub const ACCEL_SMPLRT_DIV_2: Register = Register::new(Bank::Bank2, 0x11);
+pub const ACCEL_INTEL_CTRL: Register = Register::new(Bank::Bank2, 0x12);
+pub const ACCEL_WOM_THR: Register = Register::new(Bank::Bank2, 0x13);
+pub const ACCEL_CONFIG: Register = Register::new(Bank::Bank2, 0x14);
+pub const ACCEL_CONFIG_2: Register = Register::new(Bank::Bank2, 0x15);
+pub const PRGM_START_ADDRH: Register = Register::new(Bank::Bank2, 0x50);
+pub const PRGM_START_ADDRL: Register = Register::new(Bank::Bank2, 0x51);
+pub const FSYNC_CONFIG: Register = Register::new(Bank::Bank2, 0x52);
+pub const TEMP_CONFIG: Register = Register::new(Bank::Bank2, 0x53);
+pub const MOD_CTRL_USR: Register = Register::new(Bank::Bank2, 0x54);
+
+pub const I2C_MST_ODR_CONFIG: Register = Register::new(Bank::Bank3, 0x00);
+pub const I2C_MST_CTRL: Register = Register::new(Bank::Bank3, 0x01);
+pub const I2C_MST_DELAY_CTRL: Register = Register::new(Bank::Bank3, 0x02);
+pub const I2C_SLV0_ADDR: Register = Register::new(Bank::Bank3, 0x03);
+pub const I2C_SLV0_REG: Register = Register::new(Bank::Bank3, 0x04);
+pub const I2C_SLV0_CTRL: Register = Register::new(Bank::Bank3, 0x05);
+pub const I2C_SLV0_DO: Register = Register::new(Bank::Bank3, 0x06);
+pub const I2C_SLV1_ADDR: Register = Register::new(Bank::Bank3, 0x07);
+pub const I2C_SLV1_REG: Register = Register::new(Bank::Bank3, 0x08);
+pub const I2C_SLV1_CTRL: Register = Register::new(Bank::Bank3, 0x09);
+pub const I2C_SLV1_DO: Register = Register::new(Bank::Bank3, 0x0A);
+pub const I2C_SLV2_ADDR: Register = Register::new(Bank::Bank3, 0x0B);
+pub const I2C_SLV2_REG: Register = Register::new(Bank::Bank3, 0x0C);
+pub const I2C_SLV2_CTRL: Register = Register::new(Bank::Bank3, 0x0D);
+pub const I2C_SLV2_DO: Register = Register::new(Bank::Bank3, 0x0E);
+pub const I2C_SLV3_ADDR: Register = Register::new(Bank::Bank3, 0x0F);
+pub const I2C_SLV3_REG: Register = Register::new(Bank::Bank3, 0x10);
+pub const I2C_SLV3_CTRL: Register = Register::new(Bank::Bank3, 0x11);
+pub const I2C_SLV3_DO: Register = Register::new(Bank::Bank3, 0x12);
+pub const I2C_SLV4_ADDR: Register = Register::new(Bank::Bank3, 0x13);
+pub const I2C_SLV4_REG: Register = Register::new(Bank::Bank3, 0x14);
+pub const I2C_SLV4_CTRL: Register = Register::new(Bank::Bank3, 0x15);
+pub const I2C_SLV4_DO: Register = Register::new(Bank::Bank3, 0x16);
+pub const I2C_SLV4_DI: Register = Register::new(Bank::Bank3, 0x17);
+
+//AK09916 magnetometer registers, it has no banks
+pub mod ak09916 {
+    pub const WIA1: u8 = 0x00;
+    pub const WIA2: u8 = 0x01;
+    pub const RSV2: u8 = 0x03;
+    pub const ST1: u8 = 0x10;
+    pub const HXL: u8 = 0x11;
+    pub const HXH: u8 = 0x12;
+    pub const HYL: u8 = 0x13;
+    pub const HYH: u8 = 0x14;
+    pub const HZL: u8 = 0x15;
+    pub const HZH: u8 = 0x16;
+    pub const ST2: u8 = 0x18;
+    pub const CNTL2: u8 = 0x31;
+    pub const CNTL3: u8 = 0x32;
+}
+
+//BMP280 barometer registers
+pub mod bmp280 {
+    //dig_T1..dig_P9, little endian
+    pub const CALIB00: u8 = 0x88;
+    pub const ID: u8 = 0xD0;
+    pub const RESET: u8 = 0xE0;
+    pub const STATUS: u8 = 0xF3;
+    pub const CTRL_MEAS: u8 = 0xF4;
+    pub const CONFIG: u8 = 0xF5;
+    //PRESS_MSB..TEMP_XLSB follow on consecutively
+    pub const PRESS_MSB: u8 = 0xF7;
+}
+
+//DMP memory addresses, written through MEM_BANK_SEL, MEM_START_ADDR and MEM_R_W
+pub mod dmp {
+    pub const DATA_OUT_CTL1: u16 = 4 * 16;
+    pub const DATA_OUT_CTL2: u16 = 4 * 16 + 2;
+    pub const DATA_INTR_CTL: u16 = 4 * 16 + 12;
+    pub const MOTION_EVENT_CTL: u16 = 4 * 16 + 14;
+    pub const ODR_CNTR_GEOMAG: u16 = 8 * 16;
+    pub const ODR_CNTR_QUAT6: u16 = 8 * 16 + 12;
+    pub const DATA_RDY_STATUS: u16 = 8 * 16 + 10;
+    pub const ODR_GEOMAG: u16 = 10 * 16;
+    pub const ODR_QUAT6: u16 = 10 * 16 + 12;
+    pub const ACCEL_ONLY_GAIN: u16 = 16 * 16 + 12;
+    pub const GYRO_SF: u16 = 19 * 16;
+    pub const CPASS_MTX_00: u16 = 23 * 16;
+    pub const CPASS_MTX_01: u16 = 23 * 16 + 4;
+    pub const CPASS_MTX_02: u16 = 23 * 16 + 8;
+    pub const CPASS_MTX_10: u16 = 23 * 16 + 12;
+    pub const CPASS_MTX_11: u16 = 24 * 16;
+    pub const CPASS_MTX_12: u16 = 24 * 16 + 4;
+    pub const CPASS_MTX_20: u16 = 24 * 16 + 8;
+    pub const CPASS_MTX_21: u16 = 24 * 16 + 12;
+    pub const CPASS_MTX_22: u16 = 25 * 16;
+    pub const ACC_SCALE: u16 = 30 * 16;
+    pub const FIFO_WATERMARK: u16 = 31 * 16 + 14;
+    pub const B2S_RATE: u16 = 48 * 16 + 8;
+    pub const BAC_RATE: u16 = 48 * 16 + 10;
+    pub const PEDSTD_STEPCTR: u16 = 54 * 16;
+    pub const GYRO_FULLSCALE: u16 = 72 * 16 + 12;
+    pub const ACC_SCALE2: u16 = 79 * 16 + 4;
+    pub const ACCEL_ALPHA_VAR: u16 = 91 * 16;
+    pub const ACCEL_A_VAR: u16 = 92 * 16;
+    pub const ACCEL_CAL_RATE: u16 = 94 * 16 + 4;
+    pub const CPASS_TIME_BUFFER: u16 = 112 * 16 + 14;
+    pub const B2S_MTX_00: u16 = 208 * 16;
+    pub const B2S_MTX_01: u16 = 208 * 16 + 4;
+    pub const B2S_MTX_02: u16 = 208 * 16 + 8;
+    pub const B2S_MTX_10: u16 = 208 * 16 + 12;
+    pub const B2S_MTX_11: u16 = 209 * 16;
+    pub const B2S_MTX_12: u16 = 209 * 16 + 4;
+    pub const B2S_MTX_20: u16 = 209 * 16 + 8;
+    pub const B2S_MTX_21: u16 = 209 * 16 + 12;
+    pub const B2S_MTX_22: u16 = 210 * 16;
+}
+
+//a typed view of a single register's contents
+pub trait RegisterValue: Copy {
+    const REGISTER: Register;
+
+    fn from_bits(bits: u8) -> Self;
+    fn bits(self) -> u8;
+}
+
+macro_rules! bitfield {
+    (
+        $name:ident @ $register:ident {
+            $(flag $flag:ident, $set_flag:ident: $bit:expr;)*
+            $(field $field:ident, $set_field:ident: $shift:expr, $width:expr;)*
+        }
+    ) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+        pub struct $name(pub u8);
+
+        impl $name {
+            $(
+                #[must_use]
+                pub const fn $flag(self) -> bool {
+                    self.0 & (1 << $bit) != 0
+                }
+
+                #[must_use]
+                pub const fn $set_flag(self, value: bool) -> Self {
+                    if value {
+                        Self(self.0 | (1 << $bit))
+                    } else {
+                        Self(self.0 & !(1 << $bit))
+                    }
+                }
+            )*
+            $(
+                #[must_use]
+                pub const fn $field(self) -> u8 {
+                    (self.0 >> $shift) & ((1 << $width) - 1)
+                }
+
+                #[must_use]
+                pub const fn $set_field(self, value: u8) -> Self {
+                    let mask = ((1 << $width) - 1) << $shift;
+                    Self((self.0 & !mask) | ((value << $shift) & mask))
+                }
+            )*
+        }
+
+        impl RegisterValue for $name {
+            const REGISTER: Register = $register;
+
+            fn from_bits(bits: u8) -> Self {
+                Self(bits)
+            }
+
+            fn bits(self) -> u8 {
+                self.0
+            }
+        }
+    };
+}
+
+bitfield!(UserCtrl @ USER_CTRL {
+    flag dmp_en, with_dmp_en: 7;
+    flag fifo_en, with_fifo_en: 6;
+    flag i2c_mst_en, with_i2c_mst_en: 5;
+    flag i2c_if_dis, with_i2c_if_dis: 4;
+    flag dmp_rst, with_dmp_rst: 3;
+    flag sram_rst, with_sram_rst: 2;
+    flag i2c_mst_rst, with_i2c_mst_rst: 1;
+});
+
+bitfield!(LpConfig @ LP_CONFIG {
+    flag i2c_mst_cycle, with_i2c_mst_cycle: 6;
+    flag accel_cycle, with_accel_cycle: 5;
+    flag gyro_cycle, with_gyro_cycle: 4;
+});
+
+bitfield!(PwrMgmt1 @ PWR_MGMT_1 {
+    flag device_reset, with_device_reset: 7;
+    flag sleep, with_sleep: 6;
+    flag lp_en, with_lp_en: 5;
+    flag temp_dis, with_temp_dis: 3;
+    field clksel, with_clksel: 0, 3;
+});
+
+bitfield!(PwrMgmt2 @ PWR_MGMT_2 {
+    field disable_accel, with_disable_accel: 3, 3;
+    field disable_gyro, with_disable_gyro: 0, 3;
+});
+
+bitfield!(IntPinCfg @ INT_PIN_CFG {
+    flag int1_actl, with_int1_actl: 7;
+    flag int1_open, with_int1_open: 6;
+    flag int1_latch_en, with_int1_latch_en: 5;
+    flag int_anyrd_2clear, with_int_anyrd_2clear: 4;
+    flag actl_fsync, with_actl_fsync: 3;
+    flag fsync_int_mode_en, with_fsync_int_mode_en: 2;
+    flag bypass_en, with_bypass_en: 1;
+});
+
+bitfield!(IntEnable @ INT_ENABLE {
+    flag reg_wof_en, with_reg_wof_en: 7;
+    flag wom_int_en, with_wom_int_en: 3;
+    flag pll_rdy_en, with_pll_rdy_en: 2;
+    flag dmp_int1_en, with_dmp_int1_en: 1;
+    flag i2c_mst_int_en, with_i2c_mst_int_en: 0;
+});
+
+bitfield!(FifoEn2 @ FIFO_EN_2 {
+    flag accel_fifo_en, with_accel_fifo_en: 4;
+    flag gyro_z_fifo_en, with_gyro_z_fifo_en: 3;
+    flag gyro_y_fifo_en, with_gyro_y_fifo_en: 2;
+    flag gyro_x_fifo_en, with_gyro_x_fifo_en: 1;
+    flag temp_fifo_en, with_temp_fifo_en: 0;
+});
+
+bitfield!(GyroConfig1 @ GYRO_CONFIG_1 {
+    flag fchoice, with_fchoice: 0;
+    field dlpfcfg, with_dlpfcfg: 3, 3;
+    field fs_sel, with_fs_sel: 1, 2;
+});
+
+bitfield!(GyroConfig2 @ GYRO_CONFIG_2 {
+    flag xgyro_cten, with_xgyro_cten: 5;
+    flag ygyro_cten, with_ygyro_cten: 4;
+    flag zgyro_cten, with_zgyro_cten: 3;
+    field avgcfg, with_avgcfg: 0, 3;
+});
+
+bitfield!(AccelIntelCtrl @ ACCEL_INTEL_CTRL {
+    flag accel_intel_en, with_accel_intel_en: 1;
+    flag accel_intel_mode_int, with_accel_intel_mode_int: 0;
+});
+
+bitfield!(AccelConfig @ ACCEL_CONFIG {
+    flag fchoice, with_fchoice: 0;
+    field dlpfcfg, with_dlpfcfg: 3, 3;
+    field fs_sel, with_fs_sel: 1, 2;
+});
+
+bitfield!(AccelConfig2 @ ACCEL_CONFIG_2 {
+    flag ax_st_en, with_ax_st_en: 4;
+    flag ay_st_en, with_ay_st_en: 3;
+    flag az_st_en, with_az_st_en: 2;
+    field dec3_cfg, with_dec3_cfg: 0, 2;
+});
+
+bitfield!(I2cMstCtrl @ I2C_MST_CTRL {
+    flag mult_mst_en, with_mult_mst_en: 7;
+    flag i2c_mst_p_nsr, with_i2c_mst_p_nsr: 4;
+    field i2c_mst_clk, with_i2c_mst_clk: 0, 4;
+});
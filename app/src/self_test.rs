@@ -0,0 +1,227 @@
+//ICM-20948 accel and gyro self-test
+//
+//the self-test response (output with the self-test actuation on, minus output with it off) is
+//compared with the factory response trimmed into the SELF_TEST registers in bank 1. it runs
+//at +-250dps and +-2g, which is what the factory trim is for
+
+use crate::{
+    register::{AccelConfig2, GyroConfig2, ACCEL_XOUT_H, SELF_TEST_X_ACCEL, SELF_TEST_X_GYRO},
+    AccelBandwidth, AccelRange, GyroBandwidth, GyroRange, Imc20948, ImcError, Transport,
+};
+use embedded_hal::blocking::delay::DelayMs;
+
+//samples averaged with self-test off and on
+const SAMPLES: u16 = 200;
+const SAMPLE_DELAY_MS: u32 = 1;
+//output settling after switching the self-test actuation
+const SETTLE_DELAY_MS: u32 = 20;
+
+//factory response for a SELF_TEST code of 1, each code step is 1% more
+const FACTORY_BASE: f32 = 2620.0;
+const FACTORY_STEP: f32 = 1.01;
+
+//response / factory limits
+const GYRO_MIN_RATIO: f32 = 0.5;
+const ACCEL_MIN_RATIO: f32 = 0.5;
+const ACCEL_MAX_RATIO: f32 = 1.5;
+//absolute limits in LSB when there's no factory trim, 60dps and 225..675mg
+const GYRO_MIN_RESPONSE: f32 = 60.0 * 131.0;
+const ACCEL_MIN_RESPONSE: f32 = 0.225 * 16384.0;
+const ACCEL_MAX_RESPONSE: f32 = 0.675 * 16384.0;
+
+//what self_test changes, put back afterwards
+struct SavedConfig {
+    gyro_range: GyroRange,
+    accel_range: AccelRange,
+    gyro_bandwidth: GyroBandwidth,
+    accel_bandwidth: AccelBandwidth,
+    gyro_divider: u8,
+    accel_divider: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AxisSelfTest {
+    //LSB
+    pub response: f32,
+    //LSB, None if the part has no factory trim for this axis
+    pub factory: Option<f32>,
+    pub passed: bool,
+}
+
+//x, y, z
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SelfTestReport {
+    pub gyro: [AxisSelfTest; 3],
+    pub accel: [AxisSelfTest; 3],
+}
+
+impl SelfTestReport {
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.gyro.iter().chain(self.accel.iter()).all(|a| a.passed)
+    }
+}
+
+impl<T, E> Imc20948<T, E>
+where
+    T: Transport<Error = E>,
+{
+    //run the self-test, restoring the range, filter and rate settings afterwards, even if it fails
+    pub fn self_test<D: DelayMs<u32>>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<SelfTestReport, ImcError<E>> {
+        let saved = SavedConfig {
+            gyro_range: self.gyro_range,
+            accel_range: self.accel_range,
+            gyro_bandwidth: self.gyro_bandwidth,
+            accel_bandwidth: self.accel_bandwidth,
+            gyro_divider: self.gyro_divider,
+            accel_divider: self.accel_divider,
+        };
+
+        let report = self.self_test_measure(delay);
+        let restored = self.self_test_restore(&saved, delay);
+        //the test's own error is the one worth reporting
+        let report = report?;
+        restored?;
+        Ok(report)
+    }
+
+    fn self_test_measure<D: DelayMs<u32>>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<SelfTestReport, ImcError<E>> {
+        self.set_gyro_range(GyroRange::Dps250)?;
+        self.set_accel_range(AccelRange::G2)?;
+        self.set_gyro_bandwidth(GyroBandwidth::Hz120)?;
+        self.set_accel_bandwidth(AccelBandwidth::Hz111)?;
+        self.set_gyro_sample_rate_divider(0)?;
+        self.set_accel_sample_rate_divider(0)?;
+        delay.delay_ms(SETTLE_DELAY_MS);
+
+        let (gyro_off, accel_off) = self.self_test_average(delay)?;
+
+        self.set_self_test_actuation(true)?;
+        delay.delay_ms(SETTLE_DELAY_MS);
+
+        let (gyro_on, accel_on) = self.self_test_average(delay)?;
+
+        let mut gyro_codes = [0; 3];
+        self.read_regs(SELF_TEST_X_GYRO, &mut gyro_codes)?;
+        let mut accel_codes = [0; 3];
+        self.read_regs(SELF_TEST_X_ACCEL, &mut accel_codes)?;
+
+        let mut report = SelfTestReport {
+            gyro: [AxisSelfTest {
+                response: 0.0,
+                factory: None,
+                passed: false,
+            }; 3],
+            accel: [AxisSelfTest {
+                response: 0.0,
+                factory: None,
+                passed: false,
+            }; 3],
+        };
+        for axis in 0..3 {
+            report.gyro[axis] = judge_gyro(gyro_on[axis] - gyro_off[axis], gyro_codes[axis]);
+            report.accel[axis] = judge_accel(accel_on[axis] - accel_off[axis], accel_codes[axis]);
+        }
+        Ok(report)
+    }
+
+    //actuation off and the saved settings back, every step is tried so one failed write doesn't
+    //leave the rest behind
+    fn self_test_restore<D: DelayMs<u32>>(
+        &mut self,
+        saved: &SavedConfig,
+        delay: &mut D,
+    ) -> Result<(), ImcError<E>> {
+        let results = [
+            self.set_self_test_actuation(false),
+            self.set_gyro_range(saved.gyro_range),
+            self.set_accel_range(saved.accel_range),
+            self.set_gyro_bandwidth(saved.gyro_bandwidth),
+            self.set_accel_bandwidth(saved.accel_bandwidth),
+            self.set_gyro_sample_rate_divider(saved.gyro_divider),
+            self.set_accel_sample_rate_divider(saved.accel_divider),
+        ];
+        delay.delay_ms(SETTLE_DELAY_MS);
+        results.into_iter().collect()
+    }
+
+    fn set_self_test_actuation(&mut self, on: bool) -> Result<(), ImcError<E>> {
+        self.modify(|r: GyroConfig2| {
+            r.with_xgyro_cten(on)
+                .with_ygyro_cten(on)
+                .with_zgyro_cten(on)
+        })?;
+        self.modify(|r: AccelConfig2| r.with_ax_st_en(on).with_ay_st_en(on).with_az_st_en(on))
+    }
+
+    //mean raw gyro and accel output, in the chip's own axes
+    fn self_test_average<D: DelayMs<u32>>(
+        &mut self,
+        delay: &mut D,
+    ) -> Result<([f32; 3], [f32; 3]), ImcError<E>> {
+        let mut gyro = [0.0; 3];
+        let mut accel = [0.0; 3];
+
+        for _ in 0..SAMPLES {
+            let mut buffer = [0; 12];
+            self.read_regs(ACCEL_XOUT_H, &mut buffer)?;
+
+            for axis in 0..3 {
+                accel[axis] +=
+                    f32::from(i16::from_be_bytes([buffer[axis * 2], buffer[axis * 2 + 1]]));
+                gyro[axis] += f32::from(i16::from_be_bytes([
+                    buffer[6 + axis * 2],
+                    buffer[6 + axis * 2 + 1],
+                ]));
+            }
+            delay.delay_ms(SAMPLE_DELAY_MS);
+        }
+
+        let samples = f32::from(SAMPLES);
+        Ok((gyro.map(|v| v / samples), accel.map(|v| v / samples)))
+    }
+}
+
+//factory self-test response in LSB from a SELF_TEST register code
+fn factory_response(code: u8) -> Option<f32> {
+    if code == 0 {
+        return None;
+    }
+    let mut response = FACTORY_BASE;
+    for _ in 1..code {
+        response *= FACTORY_STEP;
+    }
+    Some(response)
+}
+
+fn judge_gyro(response: f32, code: u8) -> AxisSelfTest {
+    let factory = factory_response(code);
+    let passed = factory.map_or_else(
+        || response.abs() >= GYRO_MIN_RESPONSE,
+        |factory| response / factory > GYRO_MIN_RATIO,
+    );
+    AxisSelfTest {
+        response,
+        factory,
+        passed,
+    }
+}
+
+fn judge_accel(response: f32, code: u8) -> AxisSelfTest {
+    let factory = factory_response(code);
+    let passed = factory.map_or_else(
+        || (ACCEL_MIN_RESPONSE..=ACCEL_MAX_RESPONSE).contains(&response.abs()),
+        |factory| (ACCEL_MIN_RATIO..=ACCEL_MAX_RATIO).contains(&(response / factory)),
+    );
+    AxisSelfTest {
+        response,
+        factory,
+        passed,
+    }
+}
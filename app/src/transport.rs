@@ -0,0 +1,214 @@
+//ICM-20948 register access over I2C or SPI
+//
+//the driver only ever reads and writes register runs in the selected bank, the transport
+//turns those into bus transactions. the AK09916 bypass is I2C only, over SPI the mag has to
+//go through the aux I2C master
+
+use crate::{ImcError, MAG_ADDR};
+use embedded_hal::{blocking::i2c, blocking::spi, digital::v2::OutputPin};
+#[cfg(feature = "embedded-hal-1")]
+use embedded_hal_1::i2c::{I2c, Operation};
+
+//largest single I2C write, longer write_regs runs are split
+const MAX_BURST_WRITE: usize = 16;
+//SPI register address read flag
+const SPI_READ: u8 = 0x80;
+
+pub trait Transport {
+    type Error;
+
+    //leave the chip's I2C interface enabled, SPI turns it off so it can't pick up stray traffic
+    const I2C_INTERFACE: bool;
+
+    //burst read starting at register address reg
+    fn read_regs(&mut self, reg: u8, buffer: &mut [u8]) -> Result<(), ImcError<Self::Error>>;
+
+    //burst write starting at register address reg
+    fn write_regs(&mut self, reg: u8, values: &[u8]) -> Result<(), ImcError<Self::Error>>;
+
+    //AK09916 register reads through the bypass
+    fn bypass_read_regs(&mut self, reg: u8, buffer: &mut [u8])
+        -> Result<(), ImcError<Self::Error>>;
+
+    fn bypass_write_reg(&mut self, reg: u8, value: u8) -> Result<(), ImcError<Self::Error>>;
+}
+
+pub struct I2cTransport<I> {
+    i2c: I,
+    address: i2c::SevenBitAddress,
+}
+
+impl<I> I2cTransport<I> {
+    pub const fn new(i2c: I, address: i2c::SevenBitAddress) -> Self {
+        Self { i2c, address }
+    }
+}
+
+impl<I, E> Transport for I2cTransport<I>
+where
+    I: i2c::Read<Error = E> + i2c::Write<Error = E> + i2c::WriteRead<Error = E>,
+{
+    type Error = E;
+
+    const I2C_INTERFACE: bool = true;
+
+    fn read_regs(&mut self, reg: u8, buffer: &mut [u8]) -> Result<(), ImcError<E>> {
+        self.i2c
+            .write_read(self.address, &[reg], buffer)
+            .map_err(ImcError::I2c)
+    }
+
+    fn write_regs(&mut self, mut reg: u8, values: &[u8]) -> Result<(), ImcError<E>> {
+        let mut buffer = [0; MAX_BURST_WRITE + 1];
+        //the register address auto increments, so each chunk carries on from the last
+        for chunk in values.chunks(MAX_BURST_WRITE) {
+            buffer[0] = reg;
+            buffer[1..=chunk.len()].copy_from_slice(chunk);
+
+            self.i2c
+                .write(self.address, &buffer[..=chunk.len()])
+                .map_err(ImcError::I2c)?;
+            #[allow(clippy::cast_possible_truncation)]
+            let step = MAX_BURST_WRITE as u8;
+            reg = reg.wrapping_add(step);
+        }
+        Ok(())
+    }
+
+    fn bypass_read_regs(&mut self, reg: u8, buffer: &mut [u8]) -> Result<(), ImcError<E>> {
+        self.i2c
+            .write_read(MAG_ADDR, &[reg], buffer)
+            .map_err(ImcError::I2c)
+    }
+
+    fn bypass_write_reg(&mut self, reg: u8, value: u8) -> Result<(), ImcError<E>> {
+        self.i2c
+            .write(MAG_ADDR, &[reg, value])
+            .map_err(ImcError::I2c)
+    }
+}
+
+//embedded-hal 1.0 bus, errors are split into NACK and arbitration loss by ErrorKind
+#[cfg(feature = "embedded-hal-1")]
+pub struct Eh1I2cTransport<I> {
+    i2c: I,
+    address: u8,
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<I> Eh1I2cTransport<I> {
+    pub const fn new(i2c: I, address: u8) -> Self {
+        Self { i2c, address }
+    }
+}
+
+#[cfg(feature = "embedded-hal-1")]
+impl<I: I2c> Transport for Eh1I2cTransport<I> {
+    type Error = I::Error;
+
+    const I2C_INTERFACE: bool = true;
+
+    fn read_regs(&mut self, reg: u8, buffer: &mut [u8]) -> Result<(), ImcError<I::Error>> {
+        self.i2c
+            .write_read(self.address, &[reg], buffer)
+            .map_err(ImcError::from_i2c)
+    }
+
+    fn write_regs(&mut self, reg: u8, values: &[u8]) -> Result<(), ImcError<I::Error>> {
+        //adjacent writes in a transaction go out as one, no need to copy into a buffer
+        self.i2c
+            .transaction(
+                self.address,
+                &mut [Operation::Write(&[reg]), Operation::Write(values)],
+            )
+            .map_err(ImcError::from_i2c)
+    }
+
+    fn bypass_read_regs(&mut self, reg: u8, buffer: &mut [u8]) -> Result<(), ImcError<I::Error>> {
+        self.i2c
+            .write_read(MAG_ADDR, &[reg], buffer)
+            .map_err(ImcError::from_i2c)
+    }
+
+    fn bypass_write_reg(&mut self, reg: u8, value: u8) -> Result<(), ImcError<I::Error>> {
+        self.i2c
+            .write(MAG_ADDR, &[reg, value])
+            .map_err(ImcError::from_i2c)
+    }
+}
+
+#[derive(Debug)]
+pub enum SpiError<S, P> {
+    Spi(S),
+    ChipSelect(P),
+}
+
+//mode 0 or 3, up to 7MHz
+pub struct SpiTransport<S, P> {
+    spi: S,
+    //active low
+    cs: P,
+}
+
+impl<S, P> SpiTransport<S, P> {
+    pub const fn new(spi: S, cs: P) -> Self {
+        Self { spi, cs }
+    }
+}
+
+impl<S, P, SE, PE> SpiTransport<S, P>
+where
+    S: spi::Transfer<u8, Error = SE> + spi::Write<u8, Error = SE>,
+    P: OutputPin<Error = PE>,
+{
+    //run f with chip select asserted, releasing it even if f fails. f's error comes first, a
+    //failed release is only reported when the transfer itself went through
+    fn transaction<F>(&mut self, f: F) -> Result<(), ImcError<SpiError<SE, PE>>>
+    where
+        F: FnOnce(&mut S) -> Result<(), SE>,
+    {
+        self.cs
+            .set_low()
+            .map_err(|e| ImcError::Spi(SpiError::ChipSelect(e)))?;
+        let result = f(&mut self.spi).map_err(|e| ImcError::Spi(SpiError::Spi(e)));
+        let released = self
+            .cs
+            .set_high()
+            .map_err(|e| ImcError::Spi(SpiError::ChipSelect(e)));
+        result.and(released)
+    }
+}
+
+impl<S, P, SE, PE> Transport for SpiTransport<S, P>
+where
+    S: spi::Transfer<u8, Error = SE> + spi::Write<u8, Error = SE>,
+    P: OutputPin<Error = PE>,
+{
+    type Error = SpiError<SE, PE>;
+
+    const I2C_INTERFACE: bool = false;
+
+    fn read_regs(&mut self, reg: u8, buffer: &mut [u8]) -> Result<(), ImcError<Self::Error>> {
+        self.transaction(|spi| {
+            spi.write(&[SPI_READ | reg])?;
+            buffer.fill(0);
+            spi.transfer(buffer)?;
+            Ok(())
+        })
+    }
+
+    fn write_regs(&mut self, reg: u8, values: &[u8]) -> Result<(), ImcError<Self::Error>> {
+        self.transaction(|spi| {
+            spi.write(&[reg])?;
+            spi.write(values)
+        })
+    }
+
+    fn bypass_read_regs(&mut self, _: u8, _: &mut [u8]) -> Result<(), ImcError<Self::Error>> {
+        Err(ImcError::WrongMagAccess)
+    }
+
+    fn bypass_write_reg(&mut self, _: u8, _: u8) -> Result<(), ImcError<Self::Error>> {
+        Err(ImcError::WrongMagAccess)
+    }
+}
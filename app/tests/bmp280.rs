@@ -0,0 +1,50 @@
+//BMP280 compensation against the datasheet's worked example, run on the host with cargo test-host
+#![warn(clippy::pedantic, clippy::nursery)]
+
+use imu_playground::BmpCalibration;
+
+//datasheet section 3.12
+const CALIBRATION: BmpCalibration = BmpCalibration {
+    t1: 27504,
+    t2: 26435,
+    t3: -1000,
+    p1: 36477,
+    p2: -10685,
+    p3: 3024,
+    p4: 2855,
+    p5: 140,
+    p6: -7,
+    p7: 15500,
+    p8: -14600,
+    p9: 6000,
+};
+
+#[test]
+fn temperature_matches_datasheet() {
+    let t_fine = CALIBRATION.t_fine(519_888);
+
+    assert_eq!(t_fine, 128_422);
+    assert_eq!(BmpCalibration::temperature(t_fine), 2508);
+}
+
+#[test]
+fn pressure_matches_datasheet() {
+    let pressure = CALIBRATION.pressure(415_148, 128_422).unwrap();
+
+    //100653.27Pa in Q24.8, the 64 bit integer compensation is exact
+    assert_eq!(pressure, 25_767_233);
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+fn address_follows_sdo() {
+    use embedded_hal_mock::eh0::i2c::{Mock, Transaction};
+    use imu_playground::{Bmp280, BmpAddress};
+
+    //ID
+    let mut i2c = Mock::new(&[Transaction::write_read(0x77, vec![0xD0], vec![0x58])]);
+    let mut bmp = Bmp280::new(i2c.clone(), BmpAddress::SdoHigh);
+
+    assert_eq!(bmp.who_am_i().unwrap(), 0x58);
+    i2c.done();
+}
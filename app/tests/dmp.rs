@@ -0,0 +1,199 @@
+//DMP packet parsing, and dmp_configure and dmp_read against the simulator, run on the host with
+//cargo test-host
+#![cfg(feature = "blocking")]
+#![warn(clippy::pedantic, clippy::nursery)]
+
+mod sim;
+
+use embedded_hal_mock::eh0::delay::NoopDelay;
+use imu_playground::{
+    register::dmp, Config, DmpConfig, DmpPacket, I2cTransport, Imc20948, ImuAddress,
+};
+use nalgebra::{UnitQuaternion, Vector3};
+use sim::{SimError, Simulator};
+
+type Imc = Imc20948<I2cTransport<Simulator>, SimError>;
+
+//header bits
+const ACCEL: u16 = 0x8000;
+const QUAT6: u16 = 0x0800;
+const HEADER2: u16 = 0x0008;
+//header2 bits
+const ACCEL_ACCURACY: u16 = 0x4000;
+const GYRO_ACCURACY: u16 = 0x2000;
+
+//TIMEBASE_CORRECTION_PLL, bank 1
+const TIMEBASE_CORRECTION_PLL: u8 = 0x28;
+
+//90 degrees about z
+fn quarter_turn() -> UnitQuaternion<f32> {
+    UnitQuaternion::from_axis_angle(&Vector3::z_axis(), core::f32::consts::FRAC_PI_2)
+}
+
+//x, y, z in Q30
+#[allow(clippy::cast_possible_truncation)]
+fn quat6(q: &UnitQuaternion<f32>) -> Vec<u8> {
+    [q.i, q.j, q.k]
+        .iter()
+        .flat_map(|c| ((f64::from(*c) * f64::from(1u32 << 30)).round() as i32).to_be_bytes())
+        .collect()
+}
+
+//accel and a quaternion, no header2
+fn quat6_accel_packet() -> Vec<u8> {
+    let mut packet = (ACCEL | QUAT6).to_be_bytes().to_vec();
+    packet.extend([0, 1, 0, 2, 0, 3]);
+    packet.extend(quat6(&quarter_turn()));
+    //footer
+    packet.extend([0, 0]);
+    packet
+}
+
+//a quaternion with the accel and gyro calibration state
+fn header2_packet() -> Vec<u8> {
+    let mut packet = (QUAT6 | HEADER2).to_be_bytes().to_vec();
+    packet.extend((ACCEL_ACCURACY | GYRO_ACCURACY).to_be_bytes());
+    packet.extend(quat6(&quarter_turn()));
+    packet.extend(3u16.to_be_bytes());
+    packet.extend(2u16.to_be_bytes());
+    packet.extend([0, 0]);
+    packet
+}
+
+fn configured() -> (Imc, Simulator) {
+    let sim = Simulator::default();
+    let mut imc = Imc20948::new(sim.clone(), ImuAddress::Ad0Low);
+    imc.startup(&Config::default(), &mut NoopDelay::new())
+        .unwrap();
+    imc.dmp_configure(&DmpConfig {
+        game_rotation_vector: true,
+        ..DmpConfig::default()
+    })
+    .unwrap();
+    (imc, sim)
+}
+
+fn assert_rotation(actual: Option<UnitQuaternion<f32>>, expected: &UnitQuaternion<f32>) {
+    let actual = actual.expect("no quaternion");
+    assert!(
+        actual.angle_to(expected) < 1e-4,
+        "{actual:?} != {expected:?}"
+    );
+}
+
+#[test]
+fn packet_len_counts_every_field() {
+    assert_eq!(DmpPacket::packet_len(ACCEL | QUAT6, 0), 22);
+    assert_eq!(
+        DmpPacket::packet_len(QUAT6 | HEADER2, ACCEL_ACCURACY | GYRO_ACCURACY),
+        22
+    );
+    //every header and header2 field
+    assert_eq!(DmpPacket::packet_len(0xFFF8, 0x7CC0), 136);
+}
+
+#[test]
+fn parse_quat6_and_accel() {
+    let packet = DmpPacket::parse(&quat6_accel_packet()).unwrap();
+
+    assert_rotation(packet.game_rotation, &quarter_turn());
+    assert_eq!(packet.geomagnetic_rotation, None);
+    assert_eq!(packet.accel_accuracy, None);
+}
+
+#[test]
+fn parse_header2_accuracy() {
+    let packet = DmpPacket::parse(&header2_packet()).unwrap();
+
+    assert_rotation(packet.game_rotation, &quarter_turn());
+    assert_eq!(packet.accel_accuracy, Some(3));
+    assert_eq!(packet.gyro_accuracy, Some(2));
+    assert_eq!(packet.mag_accuracy, None);
+}
+
+#[test]
+fn parse_rejects_truncated_packet() {
+    let packet = header2_packet();
+
+    assert_eq!(DmpPacket::parse(&packet[..packet.len() - 1]), None);
+    //header2 flagged but missing
+    assert_eq!(DmpPacket::parse(&packet[..3]), None);
+    assert_eq!(DmpPacket::parse(&[]), None);
+}
+
+#[test]
+fn gyro_sf_uses_reference_level() {
+    for (pll, expected) in [
+        //264446880937391 * 2^4 * (19 + 1) / 1270 / 100000
+        (0x00, 666_322_849u32),
+        //trim of -6
+        (0x86, 669_485_774),
+    ] {
+        let sim = Simulator::default();
+        let mut imc = Imc20948::new(sim.clone(), ImuAddress::Ad0Low);
+        imc.startup(&Config::default(), &mut NoopDelay::new())
+            .unwrap();
+        sim.set_register(1, TIMEBASE_CORRECTION_PLL, pll);
+
+        imc.dmp_configure(&DmpConfig::default()).unwrap();
+
+        let gyro_sf = sim.dmp_memory(dmp::GYRO_SF, 4);
+        assert_eq!(gyro_sf, expected.to_be_bytes());
+    }
+}
+
+#[test]
+fn dmp_read_returns_packets_in_order() {
+    let (mut imc, sim) = configured();
+    sim.push_fifo(&[header2_packet(), quat6_accel_packet()].concat());
+
+    let first = imc.dmp_read().unwrap().unwrap();
+    let second = imc.dmp_read().unwrap().unwrap();
+
+    assert_eq!(first.accel_accuracy, Some(3));
+    assert_rotation(second.game_rotation, &quarter_turn());
+    assert_eq!(imc.dmp_read().unwrap(), None);
+}
+
+#[test]
+fn dmp_read_resyncs_on_invalid_header() {
+    let (mut imc, sim) = configured();
+    //a header bit the DMP never sets, then a good packet that can't be trusted to be aligned
+    sim.push_fifo(&[&[0x00, 0x01][..], &quat6_accel_packet()].concat());
+
+    assert_eq!(imc.dmp_read().unwrap(), None);
+    assert_eq!(sim.fifo_len(), 0);
+
+    sim.push_fifo(&quat6_accel_packet());
+    assert_rotation(
+        imc.dmp_read().unwrap().unwrap().game_rotation,
+        &quarter_turn(),
+    );
+}
+
+#[test]
+fn dmp_read_resyncs_on_invalid_header2() {
+    let (mut imc, sim) = configured();
+    let mut packet = header2_packet();
+    packet[3] |= 0x01;
+    sim.push_fifo(&packet);
+
+    assert_eq!(imc.dmp_read().unwrap(), None);
+    assert_eq!(sim.fifo_len(), 0);
+
+    sim.push_fifo(&header2_packet());
+    assert_eq!(imc.dmp_read().unwrap().unwrap().gyro_accuracy, Some(2));
+}
+
+#[test]
+fn dmp_read_resyncs_on_overflow() {
+    let (mut imc, sim) = configured();
+    sim.push_fifo(&quat6_accel_packet()[5..]);
+    sim.set_fifo_overflow();
+
+    assert_eq!(imc.dmp_read().unwrap(), None);
+    assert_eq!(sim.fifo_len(), 0);
+
+    sim.push_fifo(&quat6_accel_packet());
+    assert!(imc.dmp_read().unwrap().is_some());
+}
@@ -0,0 +1,294 @@
+//Imc20948 against scripted I2C transactions, run on the host with cargo test-host
+#![cfg(feature = "blocking")]
+#![warn(clippy::pedantic, clippy::nursery)]
+
+use core::f32::consts::FRAC_PI_2;
+use embedded_hal_mock::eh0::{
+    delay::NoopDelay,
+    i2c::{Mock, Transaction},
+    MockError,
+};
+use imu_playground::{
+    AccelRange, Config, FifoConfig, FifoFrame, FifoMode, GyroRange, I2cTransport, Imc20948,
+    ImcError, ImuAddress, Transport,
+};
+use nalgebra::{Rotation3, Vector3};
+
+const IMU: u8 = 0x68;
+const MAG: u8 = 0x0c;
+
+fn bank(bank: u8) -> Transaction {
+    Transaction::write(IMU, vec![0x7F, bank << 4])
+}
+
+fn read(reg: u8, values: &[u8]) -> Transaction {
+    Transaction::write_read(IMU, vec![reg], values.to_vec())
+}
+
+fn write(reg: u8, value: u8) -> Transaction {
+    Transaction::write(IMU, vec![reg, value])
+}
+
+//the driver and a handle to check every transaction was used
+fn imc(expectations: &[Transaction]) -> (Imc20948<I2cTransport<Mock>, MockError>, Mock) {
+    let i2c = Mock::new(expectations);
+    (Imc20948::new(i2c.clone(), ImuAddress::Ad0Low), i2c)
+}
+
+fn assert_near(actual: Vector3<f32>, expected: Vector3<f32>) {
+    assert!(
+        (actual - expected).norm() < 1e-5,
+        "{actual:?} != {expected:?}"
+    );
+}
+
+//degrees per second to the driver's radians per second
+fn dps(x: f32, y: f32, z: f32) -> Vector3<f32> {
+    Vector3::new(x, y, z).map(f32::to_radians)
+}
+
+//ACCEL_XOUT_H..TEMP_OUT_L
+fn imu_block(acc: [i16; 3], gyro: [i16; 3], temp: i16) -> Vec<u8> {
+    acc.iter()
+        .chain(gyro.iter())
+        .chain(core::iter::once(&temp))
+        .flat_map(|v| v.to_be_bytes())
+        .collect()
+}
+
+#[test]
+fn imu_read_scales_default_ranges() {
+    let (mut imc, mut i2c) = imc(&[
+        bank(0),
+        read(0x2D, &imu_block([16384, -8192, 0], [131, -262, 0], 334)),
+    ]);
+
+    let sample = imc.imu_read().unwrap();
+
+    assert_near(sample.acc, Vector3::new(1.0, -0.5, 0.0));
+    assert_near(sample.gyro, dps(1.0, -2.0, 0.0));
+    assert!((sample.temperature - 22.0).abs() < 0.01);
+    i2c.done();
+}
+
+#[test]
+fn imu_read_scales_configured_ranges() {
+    let (mut imc, mut i2c) = imc(&[
+        //ACCEL_FS_SEL 3, GYRO_FS_SEL 3, the filter bits are kept
+        bank(2),
+        read(0x14, &[0x09]),
+        write(0x14, 0x0F),
+        read(0x01, &[0x01]),
+        write(0x01, 0x07),
+        bank(0),
+        read(0x2D, &imu_block([2048, 0, -4096], [164, 0, 0], 0)),
+    ]);
+
+    imc.set_accel_range(AccelRange::G16).unwrap();
+    imc.set_gyro_range(GyroRange::Dps2000).unwrap();
+    let sample = imc.imu_read().unwrap();
+
+    assert_near(sample.acc, Vector3::new(1.0, 0.0, -2.0));
+    assert_near(sample.gyro, dps(10.0, 0.0, 0.0));
+    assert!((sample.temperature - 21.0).abs() < 0.01);
+    i2c.done();
+}
+
+#[test]
+fn mounting_rotates_imu_and_mag() {
+    let (mut imc, mut i2c) = imc(&[
+        bank(0),
+        read(0x2D, &imu_block([16384, 0, 0], [0, 131, 0], 0)),
+        //ST1 DRDY, HX 100
+        Transaction::write_read(MAG, vec![0x10], vec![0x01, 100, 0, 0, 0, 0, 0, 0, 0]),
+    ]);
+
+    imc.set_mounting(Rotation3::from_axis_angle(&Vector3::z_axis(), FRAC_PI_2));
+    let sample = imc.imu_read().unwrap();
+    let mag = imc.mag_read().unwrap();
+
+    assert_near(sample.acc, Vector3::new(0.0, 1.0, 0.0));
+    assert_near(sample.gyro, dps(-1.0, 0.0, 0.0));
+    assert_near(mag.field, Vector3::new(0.0, 15.0, 0.0));
+    i2c.done();
+}
+
+#[test]
+fn mag_read_realigns_axes() {
+    let (mut imc, mut i2c) = imc(&[
+        //ST1 DRDY, HX 100, HY 200, HZ -300, TMPS, ST2
+        Transaction::write_read(
+            MAG,
+            vec![0x10],
+            vec![0x01, 0x64, 0x00, 0xC8, 0x00, 0xD4, 0xFE, 0x00, 0x00],
+        ),
+        //ST1 DOR, ST2 HOFL
+        Transaction::write_read(MAG, vec![0x10], vec![0x02, 0, 0, 0, 0, 0, 0, 0, 0x08]),
+    ]);
+
+    let sample = imc.mag_read().unwrap();
+    assert_near(sample.field, Vector3::new(15.0, -30.0, 45.0));
+    assert!(sample.is_valid());
+    assert!(!sample.data_overrun);
+
+    let sample = imc.mag_read().unwrap();
+    assert!(sample.data_overrun);
+    assert!(sample.overflow);
+    assert!(!sample.is_valid());
+    i2c.done();
+}
+
+#[test]
+fn startup_sequence() {
+    let (mut imc, mut i2c) = imc(&[
+        //WHO_AM_I
+        bank(0),
+        read(0x00, &[0xEA]),
+        //DEVICE_RESET, then the bank is selected again
+        read(0x06, &[0x41]),
+        write(0x06, 0xC1),
+        bank(0),
+        //wake on CLKSEL 1, all axes on
+        write(0x06, 0x01),
+        write(0x07, 0x00),
+        //I2C_MST_RST, BYPASS_EN
+        read(0x03, &[0x00]),
+        write(0x03, 0x02),
+        read(0x0F, &[0x00]),
+        write(0x0F, 0x02),
+        //AK09916 WIA1, soft reset, power down then continuous 100Hz
+        Transaction::write_read(MAG, vec![0x00], vec![0x48, 0x09]),
+        Transaction::write(MAG, vec![0x32, 0x01]),
+        Transaction::write(MAG, vec![0x31, 0x00]),
+        Transaction::write(MAG, vec![0x31, 0x08]),
+        //gyro 197Hz and accel 246Hz filters
+        bank(2),
+        read(0x01, &[0x01]),
+        write(0x01, 0x01),
+        read(0x14, &[0x01]),
+        write(0x14, 0x09),
+        //sample rate dividers
+        write(0x00, 10),
+        read(0x10, &[0x00]),
+        write(0x10, 0x00),
+        write(0x11, 10),
+        //500dps and 4g
+        read(0x01, &[0x01]),
+        write(0x01, 0x03),
+        read(0x14, &[0x09]),
+        write(0x14, 0x0B),
+    ]);
+
+    let config = Config {
+        gyro_range: GyroRange::Dps500,
+        accel_range: AccelRange::G4,
+        gyro_divider: 10,
+        accel_divider: 10,
+        ..Config::default()
+    };
+    imc.startup(&config, &mut NoopDelay::new()).unwrap();
+
+    assert_eq!(imc.gyro_range(), GyroRange::Dps500);
+    assert_eq!(imc.accel_range(), AccelRange::G4);
+    assert!((imc.gyro_odr() - 100.0).abs() < 0.01);
+    assert!((imc.accel_odr() - 1125.0 / 11.0).abs() < 0.01);
+    i2c.done();
+}
+
+#[test]
+fn startup_rejects_wrong_id() {
+    let (mut imc, mut i2c) = imc(&[bank(0), read(0x00, &[0x12])]);
+
+    let result = imc.startup(&Config::default(), &mut NoopDelay::new());
+
+    assert!(matches!(result, Err(ImcError::BadId)));
+    i2c.done();
+}
+
+#[test]
+fn bank_select_is_cached() {
+    let (mut imc, mut i2c) = imc(&[bank(0), read(0x00, &[0xEA]), read(0x00, &[0xEA])]);
+
+    assert_eq!(imc.imu_who_am_i().unwrap(), 0xEA);
+    assert_eq!(imc.imu_who_am_i().unwrap(), 0xEA);
+    i2c.done();
+}
+
+#[test]
+fn verify_reports_mismatch() {
+    let (mut imc, mut i2c) = imc(&[bank(2), write(0x00, 5), read(0x00, &[4])]);
+
+    imc.set_verify(true);
+    let result = imc.set_gyro_sample_rate_divider(5);
+
+    assert!(matches!(
+        result,
+        Err(ImcError::Verify {
+            expected: 5,
+            actual: 4,
+            ..
+        })
+    ));
+    i2c.done();
+}
+
+#[test]
+fn long_burst_write_is_split() {
+    let mut i2c = Mock::new(&[
+        Transaction::write(IMU, core::iter::once(0x10).chain(0..16).collect()),
+        Transaction::write(IMU, vec![0x20, 16, 17, 18, 19]),
+    ]);
+    let mut transport = I2cTransport::new(i2c.clone(), IMU);
+
+    let values: Vec<u8> = (0..20).collect();
+    transport.write_regs(0x10, &values).unwrap();
+    i2c.done();
+}
+
+#[test]
+fn accel_divider_out_of_range_is_rejected() {
+    let (mut imc, mut i2c) = imc(&[]);
+
+    let result = imc.set_accel_sample_rate_divider(0x1000);
+
+    assert!(matches!(result, Err(ImcError::AccelDividerRange)));
+    i2c.done();
+}
+
+#[test]
+fn full_snapshot_is_read_not_reset() {
+    let frame = imu_block([0, 0, 16384], [0; 3], 0);
+    let (mut imc, mut i2c) = imc(&[
+        //fifo_configure, accel only
+        bank(0),
+        read(0x03, &[0x00]),
+        write(0x03, 0x00),
+        write(0x66, 0x00),
+        write(0x67, 0x10),
+        write(0x69, 0x1F),
+        write(0x68, 0x1F),
+        write(0x68, 0x00),
+        read(0x03, &[0x00]),
+        write(0x03, 0x40),
+        //fifo_read, overflowed with two frames and a partial one
+        read(0x1B, &[0x01]),
+        read(0x70, &[0x00, 15]),
+        read(0x72, &[&frame[..6], &frame[..6]].concat()),
+    ]);
+
+    imc.fifo_configure(FifoConfig {
+        accel: true,
+        mode: FifoMode::Snapshot,
+        ..FifoConfig::default()
+    })
+    .unwrap();
+    let mut frames = [FifoFrame::default(); 4];
+    let read = imc.fifo_read(&mut frames).unwrap();
+
+    assert!(read.overflow);
+    assert_eq!(read.frames, 2);
+    for frame in &frames[..2] {
+        assert_near(frame.acc.unwrap(), Vector3::new(0.0, 0.0, 1.0));
+    }
+    i2c.done();
+}
@@ -0,0 +1,490 @@
+//register level ICM-20948 and AK09916 simulator on an embedded-hal 0.2 I2C bus
+//
+//the ICM-20948 keeps all four register banks with their reset values, resets on DEVICE_RESET
+//and bridges the AK09916 onto the bus when BYPASS_EN is set. the aux I2C master runs SLV4
+//transactions straight away and SLV0 reads land in EXT_SLV_SENS_DATA. the FIFO and the DMP memory
+//are plain byte stores behind their data ports. sensor outputs and FIFO contents come from
+//whatever the test injects, and injected errors fail whole transactions
+#![allow(dead_code)]
+
+use embedded_hal::blocking::i2c;
+use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+pub const IMU_ADDR: u8 = 0x68;
+pub const MAG_ADDR: u8 = 0x0c;
+
+const BANK_SIZE: usize = 0x80;
+const REG_BANK_SEL: u8 = 0x7F;
+
+//bank 0
+const USER_CTRL: u8 = 0x03;
+const LP_CONFIG: u8 = 0x05;
+const PWR_MGMT_1: u8 = 0x06;
+const INT_PIN_CFG: u8 = 0x0F;
+const I2C_MST_STATUS: u8 = 0x17;
+const INT_STATUS_2: u8 = 0x1B;
+const ACCEL_XOUT_H: u8 = 0x2D;
+const TEMP_OUT_L: u8 = 0x3A;
+const EXT_SLV_SENS_DATA_00: u8 = 0x3B;
+const EXT_SLV_SENS_DATA_23: u8 = 0x52;
+const FIFO_RST: u8 = 0x68;
+const FIFO_COUNTH: u8 = 0x70;
+const FIFO_COUNTL: u8 = 0x71;
+const FIFO_R_W: u8 = 0x72;
+const MEM_START_ADDR: u8 = 0x7C;
+const MEM_R_W: u8 = 0x7D;
+const MEM_BANK_SEL: u8 = 0x7E;
+//bank 2
+const GYRO_CONFIG_1: u8 = 0x01;
+const ACCEL_CONFIG: u8 = 0x14;
+//bank 3
+const I2C_SLV0_ADDR: u8 = 0x03;
+const I2C_SLV0_REG: u8 = 0x04;
+const I2C_SLV0_CTRL: u8 = 0x05;
+const I2C_SLV4_ADDR: u8 = 0x13;
+const I2C_SLV4_REG: u8 = 0x14;
+const I2C_SLV4_CTRL: u8 = 0x15;
+const I2C_SLV4_DO: u8 = 0x16;
+const I2C_SLV4_DI: u8 = 0x17;
+
+const DEVICE_RESET: u8 = 0x80;
+const I2C_MST_EN: u8 = 0x20;
+const I2C_MST_RST: u8 = 0x02;
+const BYPASS_EN: u8 = 0x02;
+const SLV_RNW: u8 = 0x80;
+const SLV_EN: u8 = 0x80;
+const SLV4_DONE: u8 = 0x40;
+const SLV4_NACK: u8 = 0x10;
+
+//AK09916
+const WIA1: u8 = 0x00;
+const WIA2: u8 = 0x01;
+const ST1: u8 = 0x10;
+const HXL: u8 = 0x11;
+const ST2: u8 = 0x18;
+const CNTL2: u8 = 0x31;
+const CNTL3: u8 = 0x32;
+const MAG_SIZE: usize = 0x33;
+const DRDY: u8 = 0x01;
+const DOR: u8 = 0x02;
+const HOFL: u8 = 0x08;
+const MODE_SINGLE: u8 = 0x01;
+const MODE_SELF_TEST: u8 = 0x10;
+//self-test field, inside the datasheet limits
+const SELF_TEST_FIELD: [i16; 3] = [20, -30, -500];
+//FIFO_OVERFLOW_INT
+const FIFO_OVERFLOW: u8 = 0x1F;
+//16 bit DMP addresses
+const DMP_MEM_SIZE: usize = 0x1_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimError {
+    //nothing acknowledged the address
+    Nack,
+    //injected bus fault
+    Bus,
+}
+
+#[derive(Debug)]
+struct Ak09916 {
+    regs: [u8; MAG_SIZE],
+    //raw field the next measurement picks up, AK09916 axes
+    field: [i16; 3],
+    overflow: bool,
+}
+
+impl Ak09916 {
+    fn new() -> Self {
+        let mut mag = Self {
+            regs: [0; MAG_SIZE],
+            field: [0; 3],
+            overflow: false,
+        };
+        mag.reset();
+        mag
+    }
+
+    fn reset(&mut self) {
+        self.regs = [0; MAG_SIZE];
+        self.regs[usize::from(WIA1)] = 0x48;
+        self.regs[usize::from(WIA2)] = 0x09;
+    }
+
+    fn continuous(&self) -> bool {
+        matches!(self.regs[usize::from(CNTL2)], 0x02 | 0x04 | 0x06 | 0x08)
+    }
+
+    //latch a measurement into HXL..ST2
+    fn measure(&mut self, field: [i16; 3]) {
+        if self.regs[usize::from(ST1)] & DRDY != 0 {
+            self.regs[usize::from(ST1)] |= DOR;
+        }
+        self.regs[usize::from(ST1)] |= DRDY;
+        for (n, value) in field.iter().enumerate() {
+            let [l, h] = value.to_le_bytes();
+            self.regs[usize::from(HXL) + n * 2] = l;
+            self.regs[usize::from(HXL) + n * 2 + 1] = h;
+        }
+        self.regs[usize::from(ST2)] = if self.overflow { HOFL } else { 0 };
+    }
+
+    fn read(&mut self, reg: u8) -> u8 {
+        let value = self.regs.get(usize::from(reg)).copied().unwrap_or(0);
+        //reading ST2 ends the data lock
+        if reg == ST2 {
+            self.regs[usize::from(ST1)] &= !(DRDY | DOR);
+        }
+        value
+    }
+
+    fn write(&mut self, reg: u8, value: u8) {
+        match reg {
+            CNTL2 => match value {
+                //one shot modes measure then drop back to power down
+                MODE_SINGLE => self.measure(self.field),
+                MODE_SELF_TEST => self.measure(SELF_TEST_FIELD),
+                mode => self.regs[usize::from(CNTL2)] = mode,
+            },
+            CNTL3 if value & 0x01 != 0 => self.reset(),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Icm20948 {
+    address: u8,
+    banks: [[u8; BANK_SIZE]; 4],
+    bank: usize,
+    //ACCEL_XOUT_H..TEMP_OUT_L
+    outputs: [u8; 14],
+    mag: Ak09916,
+    //register the next plain read starts at, for each device
+    pointers: [u8; 2],
+    fifo: VecDeque<u8>,
+    dmp_mem: Vec<u8>,
+    errors: VecDeque<SimError>,
+}
+
+impl Icm20948 {
+    fn reset(&mut self) {
+        self.banks = [[0; BANK_SIZE]; 4];
+        self.bank = 0;
+        self.banks[0][0x00] = 0xEA;
+        self.banks[0][usize::from(LP_CONFIG)] = 0x40;
+        self.banks[0][usize::from(PWR_MGMT_1)] = 0x41;
+        self.banks[2][usize::from(GYRO_CONFIG_1)] = 0x01;
+        self.banks[2][usize::from(ACCEL_CONFIG)] = 0x01;
+        self.fifo.clear();
+        self.dmp_mem.fill(0);
+    }
+
+    fn bank0(&self, reg: u8) -> u8 {
+        self.banks[0][usize::from(reg)]
+    }
+
+    fn bank3(&self, reg: u8) -> u8 {
+        self.banks[3][usize::from(reg)]
+    }
+
+    fn bypass(&self) -> bool {
+        self.bank0(INT_PIN_CFG) & BYPASS_EN != 0 && self.bank0(USER_CTRL) & I2C_MST_EN == 0
+    }
+
+    fn read(&mut self, reg: u8) -> u8 {
+        if reg == REG_BANK_SEL {
+            //the bank index is stored shifted, as written
+            return u8::try_from(self.bank << 4).unwrap();
+        }
+        if self.bank == 0 {
+            match reg {
+                ACCEL_XOUT_H..=TEMP_OUT_L => {
+                    return self.outputs[usize::from(reg - ACCEL_XOUT_H)];
+                }
+                EXT_SLV_SENS_DATA_00..=EXT_SLV_SENS_DATA_23 => {
+                    return self.ext_slv_sens_data(reg - EXT_SLV_SENS_DATA_00);
+                }
+                //clear on read
+                I2C_MST_STATUS | INT_STATUS_2 => {
+                    let status = self.bank0(reg);
+                    self.banks[0][usize::from(reg)] = 0;
+                    return status;
+                }
+                FIFO_COUNTH => return self.fifo_count()[0],
+                FIFO_COUNTL => return self.fifo_count()[1],
+                FIFO_R_W => return self.fifo.pop_front().unwrap_or(0),
+                MEM_R_W => {
+                    let address = self.dmp_address();
+                    return self.dmp_mem[address];
+                }
+                _ => {}
+            }
+        }
+        self.banks[self.bank][usize::from(reg)]
+    }
+
+    //SLV0 reading the mag into EXT_SLV_SENS_DATA
+    fn ext_slv_sens_data(&mut self, n: u8) -> u8 {
+        let ctrl = self.bank3(I2C_SLV0_CTRL);
+        let enabled = self.bank0(USER_CTRL) & I2C_MST_EN != 0 && ctrl & SLV_EN != 0;
+        let addr = self.bank3(I2C_SLV0_ADDR);
+        if enabled && addr == SLV_RNW | MAG_ADDR && n < ctrl & 0x0F {
+            self.mag.read(self.bank3(I2C_SLV0_REG) + n)
+        } else {
+            0
+        }
+    }
+
+    fn fifo_count(&self) -> [u8; 2] {
+        u16::try_from(self.fifo.len()).unwrap().to_be_bytes()
+    }
+
+    //MEM_BANK_SEL and MEM_START_ADDR, moving on to the next byte
+    fn dmp_address(&mut self) -> usize {
+        let start = self.bank0(MEM_START_ADDR);
+        self.banks[0][usize::from(MEM_START_ADDR)] = start.wrapping_add(1);
+        usize::from(u16::from_be_bytes([self.bank0(MEM_BANK_SEL), start]))
+    }
+
+    //FIFO_R_W and MEM_R_W bursts stay on the port
+    const fn is_port(&self, device: Device, reg: u8) -> bool {
+        matches!(device, Device::Imu) && self.bank == 0 && matches!(reg, FIFO_R_W | MEM_R_W)
+    }
+
+    fn write(&mut self, reg: u8, value: u8) {
+        if reg == REG_BANK_SEL {
+            self.bank = usize::from(value >> 4) & 0x03;
+            return;
+        }
+        if self.bank == 0 && reg == MEM_R_W {
+            let address = self.dmp_address();
+            self.dmp_mem[address] = value;
+            return;
+        }
+        self.banks[self.bank][usize::from(reg)] = value;
+
+        match (self.bank, reg) {
+            (0, PWR_MGMT_1) if value & DEVICE_RESET != 0 => self.reset(),
+            //self clearing
+            (0, USER_CTRL) => self.banks[0][usize::from(USER_CTRL)] &= !I2C_MST_RST,
+            (0, FIFO_RST) if value != 0 => self.fifo.clear(),
+            (3, I2C_SLV4_CTRL) if value & SLV_EN != 0 => self.slv4_transfer(),
+            _ => {}
+        }
+    }
+
+    fn slv4_transfer(&mut self) {
+        self.banks[3][usize::from(I2C_SLV4_CTRL)] &= !SLV_EN;
+        if self.bank0(USER_CTRL) & I2C_MST_EN == 0 {
+            return;
+        }
+
+        let addr = self.bank3(I2C_SLV4_ADDR);
+        let reg = self.bank3(I2C_SLV4_REG);
+        let status = if addr & !SLV_RNW != MAG_ADDR {
+            SLV4_NACK
+        } else if addr & SLV_RNW != 0 {
+            self.banks[3][usize::from(I2C_SLV4_DI)] = self.mag.read(reg);
+            SLV4_DONE
+        } else {
+            self.mag.write(reg, self.bank3(I2C_SLV4_DO));
+            SLV4_DONE
+        };
+        self.banks[0][usize::from(I2C_MST_STATUS)] |= status;
+    }
+
+    //which device answers address
+    fn device(&mut self, address: u8) -> Result<Device, SimError> {
+        if let Some(error) = self.errors.pop_front() {
+            return Err(error);
+        }
+        if address == self.address {
+            Ok(Device::Imu)
+        } else if address == MAG_ADDR && self.bypass() {
+            Ok(Device::Mag)
+        } else {
+            Err(SimError::Nack)
+        }
+    }
+
+    fn write_bytes(&mut self, device: Device, bytes: &[u8]) {
+        let Some((&reg, values)) = bytes.split_first() else {
+            return;
+        };
+        let mut reg = reg;
+        for &value in values {
+            match device {
+                Device::Imu => self.write(reg, value),
+                Device::Mag => self.mag.write(reg, value),
+            }
+            reg = self.next_reg(device, reg);
+        }
+        self.pointers[device as usize] = reg;
+    }
+
+    //burst reads auto increment from the register pointer
+    fn read_bytes(&mut self, device: Device, buffer: &mut [u8]) {
+        let mut reg = self.pointers[device as usize];
+        for byte in buffer {
+            *byte = match device {
+                Device::Imu => self.read(reg),
+                Device::Mag => self.mag.read(reg),
+            };
+            reg = self.next_reg(device, reg);
+        }
+        self.pointers[device as usize] = reg;
+    }
+
+    const fn next_reg(&self, device: Device, reg: u8) -> u8 {
+        if self.is_port(device, reg) {
+            reg
+        } else {
+            reg.wrapping_add(1)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Device {
+    Imu,
+    Mag,
+}
+
+//cloned handles share the one simulated chip, keep one to inject data and inspect registers
+#[derive(Debug, Clone)]
+pub struct Simulator {
+    chip: Rc<RefCell<Icm20948>>,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new(IMU_ADDR)
+    }
+}
+
+impl Simulator {
+    pub fn new(address: u8) -> Self {
+        let mut chip = Icm20948 {
+            address,
+            banks: [[0; BANK_SIZE]; 4],
+            bank: 0,
+            outputs: [0; 14],
+            mag: Ak09916::new(),
+            pointers: [0; 2],
+            fifo: VecDeque::new(),
+            dmp_mem: vec![0; DMP_MEM_SIZE],
+            errors: VecDeque::new(),
+        };
+        chip.reset();
+        Self {
+            chip: Rc::new(RefCell::new(chip)),
+        }
+    }
+
+    //raw accel output, LSB
+    pub fn set_accel(&self, raw: [i16; 3]) {
+        self.set_outputs(0, &raw);
+    }
+
+    //raw gyro output, LSB
+    pub fn set_gyro(&self, raw: [i16; 3]) {
+        self.set_outputs(6, &raw);
+    }
+
+    //raw TEMP_OUT
+    pub fn set_temperature(&self, raw: i16) {
+        self.set_outputs(12, &[raw]);
+    }
+
+    fn set_outputs(&self, offset: usize, raw: &[i16]) {
+        let mut chip = self.chip.borrow_mut();
+        for (n, value) in raw.iter().enumerate() {
+            chip.outputs[offset + n * 2..offset + n * 2 + 2].copy_from_slice(&value.to_be_bytes());
+        }
+    }
+
+    //raw mag field in the AK09916's own axes, LSB. latched straight away in the continuous
+    //modes, otherwise by the next single measurement
+    pub fn set_mag(&self, raw: [i16; 3]) {
+        let mut chip = self.chip.borrow_mut();
+        chip.mag.field = raw;
+        if chip.mag.continuous() {
+            chip.mag.measure(raw);
+        }
+    }
+
+    //ST2 HOFL on the following measurements
+    pub fn set_mag_overflow(&self, overflow: bool) {
+        self.chip.borrow_mut().mag.overflow = overflow;
+    }
+
+    //bytes for the driver to read back out of FIFO_R_W
+    pub fn push_fifo(&self, bytes: &[u8]) {
+        self.chip.borrow_mut().fifo.extend(bytes);
+    }
+
+    pub fn fifo_len(&self) -> usize {
+        self.chip.borrow().fifo.len()
+    }
+
+    //FIFO_OVERFLOW_INT until INT_STATUS_2 is next read
+    pub fn set_fifo_overflow(&self) {
+        self.chip.borrow_mut().banks[0][usize::from(INT_STATUS_2)] = FIFO_OVERFLOW;
+    }
+
+    pub fn dmp_memory(&self, address: u16, len: usize) -> Vec<u8> {
+        let address = usize::from(address);
+        self.chip.borrow().dmp_mem[address..address + len].to_vec()
+    }
+
+    //fail the next transaction, queued errors are used up one transaction each
+    pub fn fail_next(&self, error: SimError) {
+        self.chip.borrow_mut().errors.push_back(error);
+    }
+
+    pub fn register(&self, bank: usize, reg: u8) -> u8 {
+        self.chip.borrow().banks[bank][usize::from(reg)]
+    }
+
+    pub fn set_register(&self, bank: usize, reg: u8, value: u8) {
+        self.chip.borrow_mut().banks[bank][usize::from(reg)] = value;
+    }
+
+    pub fn mag_register(&self, reg: u8) -> u8 {
+        self.chip.borrow().mag.regs[usize::from(reg)]
+    }
+}
+
+impl i2c::Write for Simulator {
+    type Error = SimError;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), SimError> {
+        let mut chip = self.chip.borrow_mut();
+        let device = chip.device(address)?;
+        chip.write_bytes(device, bytes);
+        Ok(())
+    }
+}
+
+impl i2c::Read for Simulator {
+    type Error = SimError;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), SimError> {
+        let mut chip = self.chip.borrow_mut();
+        let device = chip.device(address)?;
+        chip.read_bytes(device, buffer);
+        Ok(())
+    }
+}
+
+impl i2c::WriteRead for Simulator {
+    type Error = SimError;
+
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), SimError> {
+        let mut chip = self.chip.borrow_mut();
+        let device = chip.device(address)?;
+        chip.write_bytes(device, bytes);
+        chip.read_bytes(device, buffer);
+        Ok(())
+    }
+}
@@ -0,0 +1,192 @@
+//Imc20948 driving the register level simulator in tests/sim, run on the host with cargo test-host
+#![cfg(feature = "blocking")]
+#![warn(clippy::pedantic, clippy::nursery)]
+
+mod sim;
+
+use embedded_hal_mock::eh0::delay::NoopDelay;
+use imu_playground::{
+    AccelRange, Config, GyroRange, I2cTransport, Imc20948, ImcError, ImuAddress, MagAccess, MagMode,
+};
+use nalgebra::Vector3;
+use sim::{SimError, Simulator};
+
+type Imc = Imc20948<I2cTransport<Simulator>, SimError>;
+
+//INT_ENABLE, bank 0, untouched by startup
+const INT_ENABLE: u8 = 0x10;
+//I2C_SLV0_CTRL, bank 3
+const I2C_SLV0_CTRL: u8 = 0x05;
+//GYRO_CONFIG_1 and ACCEL_CONFIG, bank 2
+const GYRO_CONFIG_1: u8 = 0x01;
+const ACCEL_CONFIG: u8 = 0x14;
+
+fn started(config: &Config) -> (Imc, Simulator) {
+    let sim = Simulator::default();
+    let mut imc = Imc20948::new(sim.clone(), ImuAddress::Ad0Low);
+    imc.startup(config, &mut NoopDelay::new()).unwrap();
+    (imc, sim)
+}
+
+fn assert_near(actual: Vector3<f32>, expected: Vector3<f32>) {
+    assert!(
+        (actual - expected).norm() < 1e-4,
+        "{actual:?} != {expected:?}"
+    );
+}
+
+#[test]
+fn startup_configures_chip() {
+    let config = Config {
+        gyro_range: GyroRange::Dps1000,
+        accel_range: AccelRange::G8,
+        verify: true,
+        ..Config::default()
+    };
+    let (_, sim) = started(&config);
+
+    //GYRO_FS_SEL 2 and ACCEL_FS_SEL 2 over the filter settings
+    assert_eq!(sim.register(2, GYRO_CONFIG_1), 0x05);
+    assert_eq!(sim.register(2, ACCEL_CONFIG), 0x0D);
+    //AK09916 CNTL2 continuous 100Hz
+    assert_eq!(sim.mag_register(0x31), MagMode::Continuous100Hz as u8);
+}
+
+#[test]
+fn startup_resets_chip() {
+    let sim = Simulator::default();
+    sim.set_register(0, INT_ENABLE, 0xFF);
+    let mut imc = Imc20948::new(sim.clone(), ImuAddress::Ad0Low);
+
+    imc.startup(&Config::default(), &mut NoopDelay::new())
+        .unwrap();
+
+    assert_eq!(sim.register(0, INT_ENABLE), 0x00);
+}
+
+#[test]
+fn startup_fails_on_absent_chip() {
+    let sim = Simulator::default();
+    let mut imc = Imc20948::new(sim, ImuAddress::Ad0High);
+
+    let result = imc.startup(&Config::default(), &mut NoopDelay::new());
+
+    assert!(matches!(result, Err(ImcError::I2c(SimError::Nack))));
+}
+
+#[test]
+fn read_all_in_bypass() {
+    let (mut imc, sim) = started(&Config::default());
+    sim.set_accel([0, 0, 16384]);
+    sim.set_gyro([-131, 0, 262]);
+    sim.set_temperature(-334);
+    sim.set_mag([100, 200, -300]);
+
+    let (imu, mag) = imc.read_all().unwrap();
+
+    assert_near(imu.acc, Vector3::new(0.0, 0.0, 1.0));
+    assert_near(imu.gyro, Vector3::new(-1.0, 0.0, 2.0).map(f32::to_radians));
+    assert!((imu.temperature - 20.0).abs() < 0.01);
+    assert_near(mag.field, Vector3::new(15.0, -30.0, 45.0));
+    assert!(mag.is_valid());
+}
+
+#[test]
+fn read_all_through_aux_master() {
+    let config = Config {
+        mag_access: MagAccess::AuxMaster,
+        ..Config::default()
+    };
+    let (mut imc, sim) = started(&config);
+    sim.set_accel([16384, 0, 0]);
+    sim.set_mag([-100, 0, 0]);
+
+    let (imu, mag) = imc.read_all().unwrap();
+
+    assert_near(imu.acc, Vector3::new(1.0, 0.0, 0.0));
+    assert_near(mag.field, Vector3::new(-15.0, 0.0, 0.0));
+    assert!(mag.is_valid());
+}
+
+#[test]
+fn mag_read_releases_data() {
+    let (mut imc, sim) = started(&Config::default());
+    sim.set_mag([10, 10, 10]);
+    sim.set_mag([20, 20, 20]);
+
+    //the second sample overran the first
+    let sample = imc.mag_read().unwrap();
+    assert!(sample.data_overrun);
+    assert_near(sample.field, Vector3::new(3.0, -3.0, -3.0));
+
+    //nothing new since ST2 was read
+    let sample = imc.mag_read().unwrap();
+    assert!(!sample.data_ready);
+}
+
+#[test]
+fn mag_overflow_is_flagged() {
+    let (mut imc, sim) = started(&Config::default());
+    sim.set_mag_overflow(true);
+    sim.set_mag([0, 0, 0]);
+
+    let sample = imc.mag_read().unwrap();
+
+    assert!(sample.overflow);
+    assert!(!sample.is_valid());
+}
+
+#[test]
+fn mag_needs_bypass() {
+    let sim = Simulator::default();
+    let mut imc = Imc20948::new(sim, ImuAddress::Ad0Low);
+
+    //BYPASS_EN is off out of reset, so the AK09916 isn't on the bus
+    let result = imc.mag_who_am_i();
+
+    assert!(matches!(result, Err(ImcError::I2c(SimError::Nack))));
+}
+
+#[test]
+fn mag_measure_in_single_mode() {
+    for mag_access in [MagAccess::Bypass, MagAccess::AuxMaster] {
+        let config = Config {
+            mag_mode: MagMode::PowerDown,
+            mag_access,
+            ..Config::default()
+        };
+        let (mut imc, sim) = started(&config);
+        sim.set_mag([0, 0, 1000]);
+
+        let sample = imc.mag_measure(&mut NoopDelay::new()).unwrap();
+
+        assert_near(sample.field, Vector3::new(0.0, 0.0, -150.0));
+        assert_eq!(imc.mag_mode(), MagMode::PowerDown);
+        //SLV0 auto read back on after the measurement, ST1..ST2
+        if mag_access == MagAccess::AuxMaster {
+            assert_eq!(sim.register(3, I2C_SLV0_CTRL), 0x89);
+        }
+    }
+}
+
+#[test]
+fn mag_self_test_passes() {
+    let (mut imc, sim) = started(&Config::default());
+
+    let result = imc.mag_self_test(&mut NoopDelay::new()).unwrap();
+
+    assert!(result.passed());
+    //back in the configured mode
+    assert_eq!(imc.mag_mode(), MagMode::Continuous100Hz);
+    assert_eq!(sim.mag_register(0x31), MagMode::Continuous100Hz as u8);
+}
+
+#[test]
+fn injected_error_fails_one_transaction() {
+    let (mut imc, sim) = started(&Config::default());
+    sim.set_accel([0, 16384, 0]);
+    sim.fail_next(SimError::Bus);
+
+    assert!(matches!(imc.imu_read(), Err(ImcError::I2c(SimError::Bus))));
+    assert_near(imc.imu_read().unwrap().acc, Vector3::new(0.0, 1.0, 0.0));
+}
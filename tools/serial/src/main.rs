@@ -19,6 +19,8 @@ struct Record {
     roll: f32,
     pitch: f32,
     yaw: f32,
+    temperature: f32,
+    altitude: f32,
 }
 
 fn main() {
@@ -43,7 +45,10 @@ fn main() {
                 .read_line(&mut discard)
                 .expect("Failed to read first line of serial data");
 
-            let mut csv_reader = csv::Reader::from_reader(serial_reader);
+            //the firmware's '#' lines, like the self-test report, aren't samples
+            let mut csv_reader = csv::ReaderBuilder::new()
+                .comment(Some(b'#'))
+                .from_reader(serial_reader);
 
             let mut r = StringRecord::new();
 
@@ -42,6 +42,8 @@ struct ImuData {
     roll: f32,
     pitch: f32,
     yaw: f32,
+    _temperature: f32,
+    _altitude: f32,
 }
 
 fn startup(
@@ -170,7 +172,10 @@ fn serial_read_loop(tx: Sender<ImuData>) -> ! {
                 .read_line(&mut discard)
                 .expect("Failed to read first line of serial data");
 
-            let mut csv_reader = csv::Reader::from_reader(serial_reader);
+            //the firmware's '#' lines, like the self-test report, aren't samples
+            let mut csv_reader = csv::ReaderBuilder::new()
+                .comment(Some(b'#'))
+                .from_reader(serial_reader);
 
             let mut r = StringRecord::new();
 